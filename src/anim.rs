@@ -0,0 +1,255 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use rustc_serialize::json::Json;
+
+use props::PropsError;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayMode {
+	Loop,
+	PingPong,
+	/// Plays through once and holds the last frame.
+	Once,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame {
+	pub id: u32,
+	/// Milliseconds the frame is shown for.
+	pub duration: f32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Animation {
+	pub frames: Vec<Frame>,
+	pub mode: PlayMode,
+}
+
+impl Animation {
+	pub fn new(frames: Vec<Frame>, mode: PlayMode) -> Animation {
+		Animation {
+			frames: frames,
+			mode: mode,
+		}
+	}
+
+	pub fn duration(&self) -> f32 {
+		self.frames.iter().map(|frame| frame.duration).sum()
+	}
+
+	/// Atlas id shown `time` milliseconds after the animation started.
+	pub fn frame_at(&self, time: f32) -> u32 {
+		let total = self.duration();
+		if self.frames.is_empty() {
+			return 0;
+		}
+		if total <= 0.0 {
+			return self.frames[0].id;
+		}
+
+		let time = match self.mode {
+			PlayMode::Loop => time % total,
+			PlayMode::Once => time.min(total - ::std::f32::EPSILON),
+			PlayMode::PingPong => {
+				// Forwards then backwards without repeating the end frames.
+				let last = self.frames[self.frames.len() - 1].duration;
+				let first = self.frames[0].duration;
+				let period = (2.0 * total - last - first).max(total);
+				let t = time % period;
+				if t < total { t } else { return self.backwards_frame(t - total); }
+			},
+		};
+
+		let mut elapsed = 0.0;
+		for frame in self.frames.iter() {
+			elapsed += frame.duration;
+			if time < elapsed {
+				return frame.id;
+			}
+		}
+		self.frames[self.frames.len() - 1].id
+	}
+
+	fn backwards_frame(&self, time: f32) -> u32 {
+		let mut elapsed = 0.0;
+		for frame in self.frames.iter().rev().skip(1) {
+			elapsed += frame.duration;
+			if time < elapsed {
+				return frame.id;
+			}
+		}
+		self.frames[0].id
+	}
+}
+
+/// All animations of an atlas, driven by one shared clock so every instance
+/// of an animated tile shows the same frame.
+pub struct AnimationSet {
+	pub animations: Vec<Animation>,
+	/// Atlas ids that animate, mapped to their animation.
+	pub by_tile: HashMap<u32, usize>,
+	pub clock: Cell<f32>,
+}
+
+impl AnimationSet {
+	pub fn new() -> AnimationSet {
+		AnimationSet {
+			animations: Vec::new(),
+			by_tile: HashMap::new(),
+			clock: Cell::new(0.0),
+		}
+	}
+
+	/// Makes every id in `tiles` play `animation`, returning its index.
+	pub fn add(&mut self, animation: Animation, tiles: &[u32]) -> usize {
+		self.animations.push(animation);
+		let index = self.animations.len() - 1;
+		for id in tiles {
+			self.by_tile.insert(*id, index);
+		}
+		index
+	}
+
+	/// Moves the shared clock on by `ms` milliseconds.
+	pub fn advance(&self, ms: f32) {
+		self.clock.set(self.clock.get() + ms);
+	}
+
+	/// Atlas id to draw for `id` right now.
+	pub fn frame(&self, id: u32) -> u32 {
+		match self.by_tile.get(&id) {
+			Some(index) => self.animations[*index].frame_at(self.clock.get()),
+			None => id,
+		}
+	}
+}
+
+/// `n` as an atlas id, if the atlas has that many entries.
+fn atlas_id(n: u64, num_entries: u32) -> Option<u32> {
+	if n < num_entries as u64 { Some(n as u32) } else { None }
+}
+
+/// Reads the `animations` section of tileset metadata:
+/// `{ "animations": [ { "tiles": [12], "mode": "pingpong", "frames": [ { "id": 12, "duration": 250 } ] } ] }`.
+/// `mode` is one of "loop" (the default), "pingpong" or "once". Every
+/// animation needs at least one frame and one tile, and some time to play.
+pub fn parse(src: &str, num_entries: u32) -> Result<AnimationSet, PropsError> {
+	let root = try!(Json::from_str(src));
+	let mut set = AnimationSet::new();
+
+	let animations = match root.find("animations").and_then(|v| v.as_array()) {
+		Some(animations) => animations,
+		None => { return Ok(set); },
+	};
+
+	for animation in animations {
+		let mode = match animation.find("mode").and_then(|v| v.as_string()) {
+			None | Some("loop") => PlayMode::Loop,
+			Some("pingpong") => PlayMode::PingPong,
+			Some("once") => PlayMode::Once,
+			Some(other) => { return Err(PropsError::Malformed(format!("unknown animation mode '{}'", other))); },
+		};
+
+		let mut frames = Vec::new();
+		for frame in animation.find("frames").and_then(|v| v.as_array()).unwrap_or(&Vec::new()) {
+			let id = try!(frame.find("id").and_then(|v| v.as_u64()).ok_or(PropsError::Malformed(String::from("animation frame without an 'id'"))));
+			let id = match atlas_id(id, num_entries) {
+				Some(id) => id,
+				None if id <= ::std::u32::MAX as u64 => { return Err(PropsError::UnknownTile(id as u32)); },
+				None => { return Err(PropsError::Malformed(format!("animation frame id {} is outside of the atlas", id))); },
+			};
+			let duration = try!(frame.find("duration").and_then(|v| v.as_f64()).ok_or(PropsError::Malformed(String::from("animation frame without a 'duration'")))) as f32;
+			if !duration.is_finite() || duration < 0.0 {
+				return Err(PropsError::Malformed(format!("bad animation frame duration {}", duration)));
+			}
+			frames.push(Frame {
+				id: id,
+				duration: duration,
+			});
+		}
+		if frames.is_empty() {
+			return Err(PropsError::Malformed(String::from("animation without frames")));
+		}
+		if frames.iter().all(|frame| frame.duration == 0.0) {
+			return Err(PropsError::Malformed(String::from("animation with no duration")));
+		}
+
+		let mut tiles = Vec::new();
+		for tile in animation.find("tiles").and_then(|v| v.as_array()).unwrap_or(&Vec::new()) {
+			match tile.as_u64().and_then(|id| atlas_id(id, num_entries)) {
+				Some(id) => tiles.push(id),
+				None => { return Err(PropsError::Malformed(String::from("bad tile id in animation"))); },
+			}
+		}
+		if tiles.is_empty() {
+			return Err(PropsError::Malformed(String::from("animation for no tiles")));
+		}
+
+		set.add(Animation::new(frames, mode), &tiles);
+	}
+
+	Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+	use anim::{parse, Animation, Frame, PlayMode};
+	use props::PropsError;
+
+	fn abc(mode: PlayMode) -> Animation {
+		let frame = |id| Frame { id: id, duration: 100.0 };
+		Animation::new(vec![frame(1), frame(2), frame(3)], mode)
+	}
+
+	fn frames(animation: &Animation, times: &[f32]) -> Vec<u32> {
+		times.iter().map(|&time| animation.frame_at(time)).collect()
+	}
+
+	#[test]
+	fn loops() {
+		let animation = abc(PlayMode::Loop);
+		assert_eq!(frames(&animation, &[0.0, 99.0, 100.0, 299.0, 300.0, 650.0]), vec![1, 1, 2, 3, 1, 1]);
+	}
+
+	#[test]
+	fn ping_pongs_without_repeating_the_ends() {
+		let animation = abc(PlayMode::PingPong);
+		// 1 2 3 2 1 2 3 2 ...
+		assert_eq!(frames(&animation, &[0.0, 150.0, 250.0, 300.0, 399.0, 400.0, 500.0, 600.0, 700.0]), vec![1, 2, 3, 2, 2, 1, 2, 3, 2]);
+
+		let two = Animation::new(vec![Frame { id: 1, duration: 100.0 }, Frame { id: 2, duration: 50.0 }], PlayMode::PingPong);
+		assert_eq!(frames(&two, &[0.0, 120.0, 150.0, 260.0]), vec![1, 2, 1, 2]);
+	}
+
+	#[test]
+	fn plays_once_and_holds() {
+		let animation = abc(PlayMode::Once);
+		assert_eq!(frames(&animation, &[0.0, 250.0, 300.0, 10000.0]), vec![1, 3, 3, 3]);
+	}
+
+	#[test]
+	fn rejects_empty_animations() {
+		let sources = [
+			r#"{ "animations": [ { "tiles": [1], "frames": [] } ] }"#,
+			r#"{ "animations": [ { "tiles": [1] } ] }"#,
+			r#"{ "animations": [ { "tiles": [], "frames": [ { "id": 1, "duration": 100 } ] } ] }"#,
+			r#"{ "animations": [ { "tiles": [1], "frames": [ { "id": 1, "duration": 0 } ] } ] }"#,
+			r#"{ "animations": [ { "tiles": [1], "frames": [ { "id": 1, "duration": -5 } ] } ] }"#,
+		];
+		for src in sources.iter() {
+			match parse(src, 16) {
+				Err(PropsError::Malformed(_)) => { },
+				_ => panic!("{} should be refused", src),
+			}
+		}
+	}
+
+	#[test]
+	fn rejects_ids_that_wrap_around() {
+		let src = r#"{ "animations": [ { "tiles": [4294967297], "frames": [ { "id": 1, "duration": 100 } ] } ] }"#;
+		assert!(parse(src, 16).is_err());
+		let src = r#"{ "animations": [ { "tiles": [1], "frames": [ { "id": 4294967297, "duration": 100 } ] } ] }"#;
+		assert!(parse(src, 16).is_err());
+	}
+}
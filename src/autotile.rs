@@ -0,0 +1,287 @@
+use rustc_serialize::json::Json;
+
+use chunk::CHUNK_SIZE;
+use map::{EdgePolicy, Map};
+use props::PropsError;
+use tile::TileAtlas;
+
+/// Neighbour bits for the 8-way blob mask. Y grows upwards, so north is y + 1.
+pub const NORTH: u8 = 1;
+pub const NORTH_EAST: u8 = 2;
+pub const EAST: u8 = 4;
+pub const SOUTH_EAST: u8 = 8;
+pub const SOUTH: u8 = 16;
+pub const SOUTH_WEST: u8 = 32;
+pub const WEST: u8 = 64;
+pub const NORTH_WEST: u8 = 128;
+
+const NEIGHBOURS: [(i32, i32, u8); 8] = [
+	(0, 1, NORTH),
+	(1, 1, NORTH_EAST),
+	(1, 0, EAST),
+	(1, -1, SOUTH_EAST),
+	(0, -1, SOUTH),
+	(-1, -1, SOUTH_WEST),
+	(-1, 0, WEST),
+	(-1, 1, NORTH_WEST),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MaskKind {
+	/// 4-bit edge mask, 16 variants indexed by N=1, E=2, S=4, W=8.
+	Cardinal,
+	/// 8-bit mask with corners ignored unless both of their edges connect,
+	/// leaving 47 variants indexed in ascending mask order.
+	Blob,
+}
+
+/// Picks the variant of a terrain from which of its neighbours are the same terrain.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AutoTileRule {
+	/// Every atlas id belonging to the terrain.
+	pub members: Vec<u32>,
+	pub kind: MaskKind,
+	/// Atlas id per mask index, 16 for `Cardinal` and 47 for `Blob`.
+	pub variants: Vec<u32>,
+	/// Whether cells off the edge of the map count as the same terrain.
+	pub connect_edges: bool,
+}
+
+impl AutoTileRule {
+	pub fn new(kind: MaskKind, variants: Vec<u32>) -> AutoTileRule {
+		let mut members = Vec::new();
+		for id in variants.iter() {
+			if !members.contains(id) {
+				members.push(*id);
+			}
+		}
+
+		AutoTileRule {
+			members: members,
+			kind: kind,
+			variants: variants,
+			connect_edges: true,
+		}
+	}
+
+	pub fn contains(&self, id: u32) -> bool {
+		self.members.contains(&id)
+	}
+
+	pub fn variant(&self, mask: u8) -> Option<u32> {
+		let index = match self.kind {
+			MaskKind::Cardinal => cardinal_index(mask),
+			MaskKind::Blob => blob_index(mask),
+		};
+		self.variants.get(index).cloned()
+	}
+}
+
+/// Reduces an 8-bit neighbour mask to the 4-bit edge index.
+pub fn cardinal_index(mask: u8) -> usize {
+	let mut index = 0;
+	if mask & NORTH != 0 { index |= 1; }
+	if mask & EAST != 0 { index |= 2; }
+	if mask & SOUTH != 0 { index |= 4; }
+	if mask & WEST != 0 { index |= 8; }
+	index
+}
+
+/// Clears corner bits whose two adjacent edges are not both set.
+pub fn reduce_blob(mask: u8) -> u8 {
+	let mut mask = mask;
+	if mask & (NORTH | EAST) != (NORTH | EAST) { mask &= !NORTH_EAST; }
+	if mask & (SOUTH | EAST) != (SOUTH | EAST) { mask &= !SOUTH_EAST; }
+	if mask & (SOUTH | WEST) != (SOUTH | WEST) { mask &= !SOUTH_WEST; }
+	if mask & (NORTH | WEST) != (NORTH | WEST) { mask &= !NORTH_WEST; }
+	mask
+}
+
+/// Position of a reduced mask among the 47 distinct blob masks.
+pub fn blob_index(mask: u8) -> usize {
+	let mask = reduce_blob(mask);
+	(0..(mask as u32)).filter(|m| reduce_blob(*m as u8) == *m as u8).count()
+}
+
+pub struct AutoTiler {
+	pub rules: Vec<AutoTileRule>,
+}
+
+impl AutoTiler {
+	pub fn new(rules: Vec<AutoTileRule>) -> AutoTiler {
+		AutoTiler {
+			rules: rules,
+		}
+	}
+
+	/// The rules from the atlas' tileset metadata, or None if it has none.
+	pub fn for_atlas(atlas: &TileAtlas) -> Option<AutoTiler> {
+		if atlas.autotile.is_empty() {
+			None
+		} else {
+			Some(AutoTiler::new(atlas.autotile.clone()))
+		}
+	}
+
+	fn rule_for(&self, id: u32) -> Option<&AutoTileRule> {
+		self.rules.iter().find(|rule| rule.contains(id))
+	}
+
+	fn mask(&self, map: &Map, rule: &AutoTileRule, layer: usize, x: i32, y: i32) -> u8 {
+		let mut mask = 0;
+		for &(dx, dy, bit) in NEIGHBOURS.iter() {
+			let (nx, ny) = (x + dx, y + dy);
+			let neighbour = if map.in_bounds(nx, ny) || map.edge == EdgePolicy::Wrap { map.get_layer(layer, nx, ny) } else { None };
+			let same = match neighbour {
+				Some(tile) => rule.contains(tile.tex_id),
+				None => rule.connect_edges,
+			};
+			if same {
+				mask |= bit;
+			}
+		}
+		mask
+	}
+
+	/// The resolved cell and the variant it should change to, if it belongs
+	/// to an autotiled terrain and isn't that variant already.
+	fn repick(&self, map: &Map, layer: usize, x: i32, y: i32) -> Option<(i32, i32, u32)> {
+		let (x, y) = match map.resolve_write(x, y) {
+			Ok(cell) => cell,
+			Err(_) => { return None; },
+		};
+		let id = match map.get_layer(layer, x, y) {
+			Some(tile) => tile.tex_id,
+			None => { return None; },
+		};
+
+		let rule = match self.rule_for(id) {
+			Some(rule) => rule,
+			None => { return None; },
+		};
+		match rule.variant(self.mask(map, rule, layer, x, y)) {
+			Some(variant) if variant != id => Some((x, y, variant)),
+			_ => None,
+		}
+	}
+
+	/// Re-picks the variant of a single cell, if it belongs to an autotiled
+	/// terrain. The change counts as an edit of the cell's chunk.
+	pub fn apply_cell(&self, map: &mut Map, layer: usize, x: i32, y: i32) {
+		if let Some((x, y, variant)) = self.repick(map, layer, x, y) {
+			let _ = map.set_layer_raw(layer, x, y, variant);
+		}
+	}
+
+	/// Like `apply_cell`, for variants that follow from what the chunk source
+	/// handed over and so don't need writing back to it.
+	fn restyle_cell(&self, map: &mut Map, layer: usize, x: i32, y: i32) {
+		if let Some((x, y, variant)) = self.repick(map, layer, x, y) {
+			map.restyle_layer(layer, x, y, variant);
+		}
+	}
+
+	/// Re-picks the cell at (x, y) and its eight neighbours after an edit.
+	pub fn apply_around(&self, map: &mut Map, layer: usize, x: i32, y: i32) {
+		for dy in -1..2 {
+			for dx in -1..2 {
+				self.apply_cell(map, layer, x + dx, y + dy);
+			}
+		}
+	}
+
+	/// Re-picks every cell of a freshly loaded chunk, and the ring of cells
+	/// around it whose neighbours just appeared, without marking any chunk edited.
+	pub fn apply_chunk(&self, map: &mut Map, layer: usize, chunk_x: i32, chunk_y: i32) {
+		for local_y in -1..(CHUNK_SIZE + 1) {
+			for local_x in -1..(CHUNK_SIZE + 1) {
+				self.restyle_cell(map, layer, chunk_x * CHUNK_SIZE + local_x, chunk_y * CHUNK_SIZE + local_y);
+			}
+		}
+	}
+
+	/// Re-picks every loaded cell of every layer, without marking any chunk edited.
+	pub fn apply_all(&self, map: &mut Map) {
+		for layer in 0..map.layers.len() {
+			for (x, y) in map.loaded_cells(layer) {
+				self.restyle_cell(map, layer, x, y);
+			}
+		}
+	}
+}
+
+/// Parses the optional autotiling rules from tileset metadata, of the form
+/// `{ "autotile": [ { "kind": "cardinal", "variants": [16 ids], "connect_edges": true } ] }`.
+/// `kind` is "cardinal" or "blob" (47 ids), and `connect_edges` defaults to true.
+pub fn parse(src: &str, num_entries: u32) -> Result<Vec<AutoTileRule>, PropsError> {
+	let root = try!(Json::from_str(src));
+	let mut rules = Vec::new();
+
+	let entries = match root.find("autotile") {
+		Some(entries) => match entries.as_array() {
+			Some(entries) => entries,
+			None => { return Err(PropsError::Malformed(String::from("'autotile' is not a list"))); },
+		},
+		None => { return Ok(rules); },
+	};
+
+	for entry in entries {
+		let (kind, count) = match entry.find("kind").and_then(|v| v.as_string()) {
+			Some("cardinal") => (MaskKind::Cardinal, 16),
+			Some("blob") => (MaskKind::Blob, 47),
+			_ => { return Err(PropsError::Malformed(String::from("autotile rule without a 'kind' of cardinal or blob"))); },
+		};
+
+		let mut variants = Vec::with_capacity(count);
+		if let Some(ids) = entry.find("variants").and_then(|v| v.as_array()) {
+			for id in ids {
+				match id.as_u64() {
+					Some(id) if id < num_entries as u64 => variants.push(id as u32),
+					Some(id) => { return Err(PropsError::UnknownTile(id as u32)); },
+					None => { return Err(PropsError::Malformed(String::from("non-integer autotile variant"))); },
+				}
+			}
+		}
+		if variants.len() != count {
+			return Err(PropsError::Malformed(format!("autotile rule needs {} variants, not {}", count, variants.len())));
+		}
+
+		let mut rule = AutoTileRule::new(kind, variants);
+		if let Some(connect) = entry.find("connect_edges").and_then(|v| v.as_boolean()) {
+			rule.connect_edges = connect;
+		}
+		rules.push(rule);
+	}
+
+	Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+	use autotile::{parse, MaskKind};
+	use props::PropsError;
+
+	#[test]
+	fn parses_rules_from_metadata() {
+		let src = r#"{ "tiles": [], "autotile": [ { "kind": "cardinal", "variants": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], "connect_edges": false } ] }"#;
+		let rules = parse(src, 16).unwrap();
+		assert_eq!(rules.len(), 1);
+		assert_eq!(rules[0].kind, MaskKind::Cardinal);
+		assert!(!rules[0].connect_edges);
+		assert_eq!(rules[0].variant(0), Some(0));
+
+		assert!(parse(r#"{ "tiles": [] }"#, 16).unwrap().is_empty());
+	}
+
+	#[test]
+	fn refuses_short_or_unknown_variants() {
+		match parse(r#"{ "autotile": [ { "kind": "blob", "variants": [1, 2] } ] }"#, 16) {
+			Err(PropsError::Malformed(_)) => { },
+			other => panic!("expected a malformed rule, got {:?}", other),
+		}
+		match parse(r#"{ "autotile": [ { "kind": "cardinal", "variants": [99] } ] }"#, 16) {
+			Err(PropsError::UnknownTile(99)) => { },
+			other => panic!("expected an unknown tile, got {:?}", other),
+		}
+	}
+}
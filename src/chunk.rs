@@ -0,0 +1,79 @@
+use map::MapError;
+use tile::{Tile, TileAtlas, EMPTY_TILE};
+use utils::translate;
+
+pub const CHUNK_SIZE: i32 = 32;
+
+/// Chunks around the view that are kept loaded.
+pub const LOAD_MARGIN: i32 = 1;
+/// Chunks further than this from the view are unloaded.
+pub const UNLOAD_MARGIN: i32 = 3;
+
+/// One CHUNK_SIZE square of a layer.
+pub struct Chunk<'a> {
+	pub tiles: Vec<Tile<'a>>,
+	pub dirty: bool,
+}
+
+impl<'a> Chunk<'a> {
+	/// Builds a chunk from CHUNK_SIZE * CHUNK_SIZE row-major atlas ids,
+	/// refusing any other number of them.
+	pub fn new(ids: Vec<u32>, atlas: &'a TileAtlas) -> Result<Chunk<'a>, MapError> {
+		if ids.len() != (CHUNK_SIZE * CHUNK_SIZE) as usize {
+			return Err(MapError::BadChunk(ids.len()));
+		}
+
+		let mut tiles = Vec::with_capacity(ids.len());
+		for id in ids {
+			tiles.push(Tile::new(id, atlas));
+		}
+
+		Ok(Chunk {
+			tiles: tiles,
+			dirty: false,
+		})
+	}
+
+	pub fn empty(atlas: &'a TileAtlas) -> Chunk<'a> {
+		Chunk {
+			tiles: (0..CHUNK_SIZE * CHUNK_SIZE).map(|_| Tile::new(EMPTY_TILE, atlas)).collect(),
+			dirty: false,
+		}
+	}
+
+	pub fn get(&self, local_x: i32, local_y: i32) -> &Tile<'a> {
+		&self.tiles[translate(local_x, local_y, CHUNK_SIZE)]
+	}
+
+	pub fn get_mut(&mut self, local_x: i32, local_y: i32) -> &mut Tile<'a> {
+		&mut self.tiles[translate(local_x, local_y, CHUNK_SIZE)]
+	}
+
+	pub fn ids(&self) -> Vec<u32> {
+		self.tiles.iter().map(|tile| tile.tex_id).collect()
+	}
+}
+
+/// Supplies chunks to a streaming map, by generating them or reading them from disk.
+pub trait ChunkSource {
+	/// Atlas ids for one layer of a chunk, CHUNK_SIZE * CHUNK_SIZE of them in
+	/// row-major order, or None to leave that part of the layer empty. Any
+	/// other number of ids is refused and the chunk left unloaded.
+	fn load(&mut self, layer: usize, chunk_x: i32, chunk_y: i32) -> Option<Vec<u32>>;
+
+	/// Called with chunks that were edited as they are unloaded, so they can be written back.
+	fn unload(&mut self, _layer: usize, _chunk_x: i32, _chunk_y: i32, _ids: &[u32]) {
+	}
+}
+
+pub fn floor_div(a: i32, b: i32) -> i32 {
+	let d = a / b;
+	if (a % b != 0) && ((a < 0) != (b < 0)) { d - 1 } else { d }
+}
+
+/// Splits a signed world coordinate into the chunk holding it and the offset inside that chunk.
+pub fn split(x: i32, y: i32) -> ((i32, i32), (i32, i32)) {
+	let chunk_x = floor_div(x, CHUNK_SIZE);
+	let chunk_y = floor_div(y, CHUNK_SIZE);
+	((chunk_x, chunk_y), (x - chunk_x * CHUNK_SIZE, y - chunk_y * CHUNK_SIZE))
+}
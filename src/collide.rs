@@ -0,0 +1,215 @@
+//! Axis-separated swept AABB collision against solid tiles.
+//!
+//! A move is resolved along x first, then y. Along each axis the box is
+//! pushed through the columns or rows ahead of it one at a time and stopped
+//! flush against the first one holding a solid tile, so fast bodies can't
+//! tunnel through thin walls. One-way tiles only stop bodies falling onto
+//! them from above.
+
+use map::Map;
+
+/// An axis-aligned box in world tiles, (x, y) being its bottom-left corner.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+	pub x: f32,
+	pub y: f32,
+	pub width: f32,
+	pub height: f32,
+}
+
+impl Aabb {
+	pub fn new(x: f32, y: f32, width: f32, height: f32) -> Aabb {
+		Aabb {
+			x: x,
+			y: y,
+			width: width,
+			height: height,
+		}
+	}
+}
+
+/// Which sides of a body were stopped by tiles during a move.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Contacts {
+	pub grounded: bool,
+	pub wall_left: bool,
+	pub wall_right: bool,
+	pub ceiling: bool,
+}
+
+/// Cells spanned by [min, max), an edge exactly on a boundary not reaching into the next cell.
+fn span(min: f32, max: f32) -> (i32, i32) {
+	(min.floor() as i32, (max.ceil() as i32) - 1)
+}
+
+impl<'a> Map<'a> {
+	fn blocks_body(&self, x: i32, y: i32, falling_onto: bool) -> bool {
+		(0..self.layers.len()).any(|layer| match self.props_layer(layer, x, y) {
+			Some(props) => props.solid && (!props.one_way || falling_onto),
+			None => false,
+		})
+	}
+
+	/// Moves `body` by (dx, dy), stopping it against solid tiles. Returns where
+	/// it ended up and what it touched.
+	pub fn sweep(&self, body: &Aabb, dx: f32, dy: f32) -> (Aabb, Contacts) {
+		let mut body = *body;
+		let mut contacts = Contacts::default();
+
+		// Only the columns and rows the body moves into are checked, nearest first.
+		if dx > 0.0 {
+			let rows = span(body.y, body.y + body.height);
+			let right = body.x + body.width;
+			body.x += dx;
+			for col in (right.ceil() as i32)..((right + dx).ceil() as i32) {
+				if (rows.0..(rows.1 + 1)).any(|row| self.blocks_body(col, row, false)) {
+					body.x = col as f32 - body.width;
+					contacts.wall_right = true;
+					break;
+				}
+			}
+		} else if dx < 0.0 {
+			let rows = span(body.y, body.y + body.height);
+			let left = body.x;
+			body.x += dx;
+			for col in (((left + dx).floor() as i32)..(left.floor() as i32)).rev() {
+				if (rows.0..(rows.1 + 1)).any(|row| self.blocks_body(col, row, false)) {
+					body.x = col as f32 + 1.0;
+					contacts.wall_left = true;
+					break;
+				}
+			}
+		}
+
+		if dy > 0.0 {
+			let cols = span(body.x, body.x + body.width);
+			let top = body.y + body.height;
+			body.y += dy;
+			for row in (top.ceil() as i32)..((top + dy).ceil() as i32) {
+				if (cols.0..(cols.1 + 1)).any(|col| self.blocks_body(col, row, false)) {
+					body.y = row as f32 - body.height;
+					contacts.ceiling = true;
+					break;
+				}
+			}
+		} else if dy < 0.0 {
+			let cols = span(body.x, body.x + body.width);
+			let bottom = body.y;
+			body.y += dy;
+			for row in (((bottom + dy).floor() as i32)..(bottom.floor() as i32)).rev() {
+				// Every row checked is below the body's feet, so one-way tiles count.
+				if (cols.0..(cols.1 + 1)).any(|col| self.blocks_body(col, row, true)) {
+					body.y = row as f32 + 1.0;
+					contacts.grounded = true;
+					break;
+				}
+			}
+		}
+
+		(body, contacts)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use collide::{Aabb, Contacts};
+	use map::Map;
+	use tile::{TileAtlas, EMPTY_TILE};
+
+	const BRICK: u32 = 11;
+	const PLATFORM: u32 = 15;
+
+	fn blank(atlas: &TileAtlas) -> Map {
+		Map::from_ids(20, 20, vec![EMPTY_TILE; 400], Vec::new(), 10.0, 1.0, atlas)
+	}
+
+	fn wall(map: &mut Map, x: i32) {
+		for y in 0..20 {
+			map.set(x, y, BRICK).unwrap();
+		}
+	}
+
+	#[test]
+	fn lands_on_the_floor() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		map.set(2, 1, BRICK).unwrap();
+
+		let (body, contacts) = map.sweep(&Aabb::new(2.0, 3.5, 1.0, 1.0), 0.0, -2.0);
+		assert_eq!(body, Aabb::new(2.0, 2.0, 1.0, 1.0));
+		assert_eq!(contacts, Contacts { grounded: true, ..Contacts::default() });
+
+		// Standing on it, a further push down goes nowhere.
+		let (body, contacts) = map.sweep(&body, 0.0, -0.5);
+		assert_eq!(body.y, 2.0);
+		assert!(contacts.grounded);
+	}
+
+	#[test]
+	fn slides_down_a_wall() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		wall(&mut map, 4);
+
+		let (body, contacts) = map.sweep(&Aabb::new(2.0, 5.0, 1.0, 1.0), 1.5, -0.5);
+		assert_eq!(body, Aabb::new(3.0, 4.5, 1.0, 1.0));
+		assert_eq!(contacts, Contacts { wall_right: true, ..Contacts::default() });
+
+		// Flush against it, moving along it isn't blocked.
+		let (body, contacts) = map.sweep(&body, 0.0, 3.0);
+		assert_eq!(body, Aabb::new(3.0, 7.5, 1.0, 1.0));
+		assert_eq!(contacts, Contacts::default());
+	}
+
+	#[test]
+	fn resolves_corners_along_x_first() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		map.set(4, 4, BRICK).unwrap();
+
+		// The brick only lies across the diagonal: x is clear, then y hits it.
+		let (body, contacts) = map.sweep(&Aabb::new(2.0, 2.0, 1.0, 1.0), 1.5, 1.5);
+		assert_eq!(body, Aabb::new(3.5, 3.0, 1.0, 1.0));
+		assert_eq!(contacts, Contacts { ceiling: true, ..Contacts::default() });
+
+		// Corner to corner, touching only at a point, nothing is hit.
+		let (body, contacts) = map.sweep(&Aabb::new(2.0, 2.0, 1.0, 1.0), 1.0, 1.0);
+		assert_eq!(body, Aabb::new(3.0, 3.0, 1.0, 1.0));
+		assert_eq!(contacts, Contacts::default());
+	}
+
+	#[test]
+	fn fast_moves_do_not_tunnel() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		wall(&mut map, 10);
+
+		let (body, contacts) = map.sweep(&Aabb::new(0.5, 2.0, 1.0, 1.0), 50.0, 0.0);
+		assert_eq!(body.x, 9.0);
+		assert!(contacts.wall_right);
+
+		let (body, contacts) = map.sweep(&Aabb::new(15.5, 2.0, 1.0, 1.0), -50.0, 0.0);
+		assert_eq!(body.x, 11.0);
+		assert!(contacts.wall_left);
+
+		map.set(3, 8, BRICK).unwrap();
+		let (body, contacts) = map.sweep(&Aabb::new(3.0, 15.0, 1.0, 1.0), 0.0, -100.0);
+		assert_eq!(body.y, 9.0);
+		assert!(contacts.grounded);
+	}
+
+	#[test]
+	fn one_way_platforms_only_stop_falls() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		map.set(3, 5, PLATFORM).unwrap();
+
+		let (body, contacts) = map.sweep(&Aabb::new(3.0, 3.0, 1.0, 1.0), 0.0, 4.0);
+		assert_eq!(body.y, 7.0);
+		assert_eq!(contacts, Contacts::default());
+
+		let (body, contacts) = map.sweep(&body, 0.0, -4.0);
+		assert_eq!(body.y, 6.0);
+		assert!(contacts.grounded);
+	}
+}
@@ -0,0 +1,472 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use rustc_serialize::json::{self, Json};
+
+use keyboard::{Action, Inputs};
+use map::Map;
+use player::{Direction, Player};
+use replay::Checksum;
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Io(io::Error),
+	Json(json::BuilderError),
+	Malformed(String),
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ConfigError::Io(ref e) => write!(f, "io error: {}", e),
+			ConfigError::Json(ref e) => write!(f, "json parse error: {}", e),
+			ConfigError::Malformed(ref s) => write!(f, "malformed controller config: {}", s),
+		}
+	}
+}
+
+impl From<io::Error> for ConfigError {
+	fn from(e: io::Error) -> ConfigError {
+		ConfigError::Io(e)
+	}
+}
+
+impl From<json::BuilderError> for ConfigError {
+	fn from(e: json::BuilderError) -> ConfigError {
+		ConfigError::Json(e)
+	}
+}
+
+/// What the player is asking for this step.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ControlInput {
+	pub left: bool,
+	pub right: bool,
+	pub up: bool,
+	pub down: bool,
+	/// Held, not just pressed; controllers find the edges themselves.
+	pub jump: bool,
+}
+
+impl ControlInput {
+	pub fn from_inputs(inputs: &Inputs) -> ControlInput {
+		ControlInput {
+			left: inputs.is_pressed(Action::Left),
+			right: inputs.is_pressed(Action::Right),
+			up: inputs.is_pressed(Action::Up),
+			down: inputs.is_pressed(Action::Down),
+			jump: inputs.is_pressed(Action::Space),
+		}
+	}
+
+	/// -1, 0 or 1 along x.
+	pub fn axis_x(&self) -> f32 {
+		(self.right as i32 - self.left as i32) as f32
+	}
+
+	/// -1, 0 or 1 along y.
+	pub fn axis_y(&self) -> f32 {
+		(self.up as i32 - self.down as i32) as f32
+	}
+}
+
+/// Moves a player each simulation step.
+pub trait Controller {
+	/// Advances the player by `dt` seconds.
+	fn update(&mut self, player: &mut Player, map: &Map, input: &ControlInput, dt: f32);
+
+	/// Feeds whatever state carries over between updates into a replay checksum.
+	fn checksum(&self, sum: &mut Checksum);
+}
+
+/// Which controller a map is meant to be played with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ControlStyle {
+	Platformer,
+	TopDown,
+}
+
+impl ControlStyle {
+	/// A controller of this style with the default tuning.
+	pub fn controller(&self) -> Box<Controller> {
+		match *self {
+			ControlStyle::Platformer => Box::new(PlatformerController::new(PlatformerConfig::new())),
+			ControlStyle::TopDown => Box::new(TopDownController::new(TopDownConfig::new())),
+		}
+	}
+}
+
+/// A number that must be present, finite and not negative.
+fn field(root: &Json, name: &str) -> Result<f32, ConfigError> {
+	match root.find(name).and_then(|v| v.as_f64()) {
+		Some(value) if (value as f32).is_finite() && value >= 0.0 => Ok(value as f32),
+		Some(value) => Err(ConfigError::Malformed(format!("'{}' must be a finite number of at least 0, not {}", name, value))),
+		None => Err(ConfigError::Malformed(format!("missing '{}'", name))),
+	}
+}
+
+/// Like `field`, for fractions between 0 and 1.
+fn fraction(root: &Json, name: &str) -> Result<f32, ConfigError> {
+	let value = try!(field(root, name));
+	if value > 1.0 {
+		return Err(ConfigError::Malformed(format!("'{}' must be at most 1, not {}", name, value)));
+	}
+	Ok(value)
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Result<String, ConfigError> {
+	let mut src = String::new();
+	try!(try!(File::open(path)).read_to_string(&mut src));
+	Ok(src)
+}
+
+/// Moves `value` towards `target` by at most `step`.
+fn approach(value: f32, target: f32, step: f32) -> f32 {
+	if value < target {
+		(value + step).min(target)
+	} else {
+		(value - step).max(target)
+	}
+}
+
+/// Tuning for `PlatformerController`. Speeds are in tiles per second, times in seconds.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PlatformerConfig {
+	pub gravity: f32,
+	pub jump_speed: f32,
+	/// Fraction of upward speed kept when jump is released early.
+	pub jump_cut: f32,
+	/// How long after walking off a ledge a jump still works.
+	pub coyote_time: f32,
+	/// How long before landing a jump press is remembered.
+	pub jump_buffer: f32,
+	pub max_fall_speed: f32,
+	pub run_speed: f32,
+	/// Horizontal acceleration on the ground, tiles per second squared.
+	pub acceleration: f32,
+	/// Fraction of `acceleration` available in the air.
+	pub air_control: f32,
+}
+
+impl PlatformerConfig {
+	/// The defaults built in from assets/player.json.
+	pub fn new() -> PlatformerConfig {
+		PlatformerConfig::parse(include_str!("../assets/player.json")).expect("assets/player.json is not a valid PlatformerConfig")
+	}
+
+	/// Parses a config of the form `{ "gravity": 60.0, "jump_speed": 18.0, ... }`
+	/// with every field present and none negative. `jump_cut` and `air_control`
+	/// are at most 1.
+	pub fn parse(src: &str) -> Result<PlatformerConfig, ConfigError> {
+		let root = try!(Json::from_str(src));
+
+		Ok(PlatformerConfig {
+			gravity: try!(field(&root, "gravity")),
+			jump_speed: try!(field(&root, "jump_speed")),
+			jump_cut: try!(fraction(&root, "jump_cut")),
+			coyote_time: try!(field(&root, "coyote_time")),
+			jump_buffer: try!(field(&root, "jump_buffer")),
+			max_fall_speed: try!(field(&root, "max_fall_speed")),
+			run_speed: try!(field(&root, "run_speed")),
+			acceleration: try!(field(&root, "acceleration")),
+			air_control: try!(fraction(&root, "air_control")),
+		})
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<PlatformerConfig, ConfigError> {
+		PlatformerConfig::parse(&try!(read_file(path)))
+	}
+}
+
+/// Side-on movement: running, gravity and jumping, with coyote time, jump
+/// buffering and shorter jumps when jump is let go early.
+pub struct PlatformerController {
+	pub config: PlatformerConfig,
+	/// Time left to jump after leaving the ground.
+	coyote: f32,
+	/// Time left on a jump press waiting to land.
+	buffered: f32,
+	jump_held: bool,
+	/// True while rising from a jump that can still be cut short.
+	rising: bool,
+}
+
+impl PlatformerController {
+	pub fn new(config: PlatformerConfig) -> PlatformerController {
+		PlatformerController {
+			config: config,
+			coyote: 0.0,
+			buffered: 0.0,
+			jump_held: false,
+			rising: false,
+		}
+	}
+}
+
+impl Controller for PlatformerController {
+	fn update(&mut self, player: &mut Player, map: &Map, input: &ControlInput, dt: f32) {
+		let config = &self.config;
+
+		if player.contacts.grounded {
+			self.coyote = config.coyote_time;
+		} else {
+			self.coyote -= dt;
+		}
+
+		if input.jump && !self.jump_held {
+			self.buffered = config.jump_buffer;
+		} else {
+			self.buffered -= dt;
+		}
+		self.jump_held = input.jump;
+
+		let axis = input.axis_x();
+		if axis < 0.0 {
+			player.dir = Direction::Left;
+		} else if axis > 0.0 {
+			player.dir = Direction::Right;
+		}
+
+		let acceleration = if player.contacts.grounded { config.acceleration } else { config.acceleration * config.air_control };
+		player.vel.x = approach(player.vel.x, axis * config.run_speed, acceleration * dt);
+
+		if self.buffered > 0.0 && self.coyote > 0.0 {
+			player.vel.y = config.jump_speed;
+			self.buffered = 0.0;
+			self.coyote = 0.0;
+			self.rising = true;
+		}
+
+		if self.rising && (player.vel.y <= 0.0 || !input.jump) {
+			if player.vel.y > 0.0 {
+				player.vel.y *= config.jump_cut;
+			}
+			self.rising = false;
+		}
+
+		player.vel.y = (player.vel.y - config.gravity * dt).max(-config.max_fall_speed);
+
+		let (dx, dy) = (player.vel.x * dt, player.vel.y * dt);
+		player.slide(map, dx, dy);
+	}
+
+	fn checksum(&self, sum: &mut Checksum) {
+		sum.write_f32(self.coyote);
+		sum.write_f32(self.buffered);
+		sum.write_u8(self.jump_held as u8);
+		sum.write_u8(self.rising as u8);
+	}
+}
+
+/// Tuning for `TopDownController`, in tiles per second and tiles per second squared.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TopDownConfig {
+	pub max_speed: f32,
+	pub acceleration: f32,
+	/// Slowing down when no direction is held.
+	pub deceleration: f32,
+}
+
+impl TopDownConfig {
+	/// The defaults built in from assets/top_down.json.
+	pub fn new() -> TopDownConfig {
+		TopDownConfig::parse(include_str!("../assets/top_down.json")).expect("assets/top_down.json is not a valid TopDownConfig")
+	}
+
+	/// Parses a config of the form `{ "max_speed": 6.0, "acceleration": 50.0, "deceleration": 40.0 }`,
+	/// none of them negative.
+	pub fn parse(src: &str) -> Result<TopDownConfig, ConfigError> {
+		let root = try!(Json::from_str(src));
+
+		Ok(TopDownConfig {
+			max_speed: try!(field(&root, "max_speed")),
+			acceleration: try!(field(&root, "acceleration")),
+			deceleration: try!(field(&root, "deceleration")),
+		})
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<TopDownConfig, ConfigError> {
+		TopDownConfig::parse(&try!(read_file(path)))
+	}
+}
+
+/// Eight-way movement seen from above, with no gravity. Diagonals are as
+/// fast as straight lines.
+pub struct TopDownController {
+	pub config: TopDownConfig,
+}
+
+impl TopDownController {
+	pub fn new(config: TopDownConfig) -> TopDownController {
+		TopDownController {
+			config: config,
+		}
+	}
+}
+
+impl Controller for TopDownController {
+	fn update(&mut self, player: &mut Player, map: &Map, input: &ControlInput, dt: f32) {
+		let config = &self.config;
+
+		let (mut x, mut y) = (input.axis_x(), input.axis_y());
+		if x != 0.0 && y != 0.0 {
+			let length = (x * x + y * y).sqrt();
+			x /= length;
+			y /= length;
+		}
+
+		if x < 0.0 {
+			player.dir = Direction::Left;
+		} else if x > 0.0 {
+			player.dir = Direction::Right;
+		} else if y > 0.0 {
+			player.dir = Direction::Up;
+		} else if y < 0.0 {
+			player.dir = Direction::Down;
+		}
+
+		let rate_x = if x == 0.0 { config.deceleration } else { config.acceleration };
+		let rate_y = if y == 0.0 { config.deceleration } else { config.acceleration };
+		player.vel.x = approach(player.vel.x, x * config.max_speed, rate_x * dt);
+		player.vel.y = approach(player.vel.y, y * config.max_speed, rate_y * dt);
+
+		let (dx, dy) = (player.vel.x * dt, player.vel.y * dt);
+		player.slide(map, dx, dy);
+	}
+
+	fn checksum(&self, _sum: &mut Checksum) { }
+}
+
+#[cfg(test)]
+mod tests {
+	use controller::{ControlInput, Controller, PlatformerConfig, PlatformerController, TopDownConfig};
+	use map::Map;
+	use player::Player;
+	use tile::{TileAtlas, EMPTY_TILE};
+	use utils::V2;
+
+	const DT: f32 = 0.02;
+	const BRICK: u32 = 11;
+
+	fn config() -> PlatformerConfig {
+		PlatformerConfig {
+			gravity: 50.0,
+			jump_speed: 15.0,
+			jump_cut: 0.5,
+			coyote_time: 0.1,
+			jump_buffer: 0.1,
+			max_fall_speed: 100.0,
+			run_speed: 5.0,
+			acceleration: 100.0,
+			air_control: 1.0,
+		}
+	}
+
+	fn blank(atlas: &TileAtlas) -> Map {
+		Map::from_ids(20, 20, vec![EMPTY_TILE; 400], Vec::new(), 10.0, 1.0, atlas)
+	}
+
+	fn step(controller: &mut PlatformerController, player: &mut Player, map: &Map, jump: bool) {
+		player.begin_tick();
+		controller.update(player, map, &ControlInput { jump: jump, ..ControlInput::default() }, DT);
+	}
+
+	/// Highest the player gets when jump is held for `held` ticks of a jump from the floor.
+	fn peak(held: usize) -> f32 {
+		let atlas = TileAtlas::new(16, 16);
+		let map = blank(&atlas);
+		let mut player = Player::new(vec![0; 4], &atlas, V2::new(5.0, 0.0));
+		let mut controller = PlatformerController::new(config());
+		step(&mut controller, &mut player, &map, false);
+
+		let mut peak = 0.0f32;
+		for tick in 0..200 {
+			step(&mut controller, &mut player, &map, tick < held);
+			peak = peak.max(player.pos.y);
+		}
+		peak
+	}
+
+	/// A player standing on a brick at (5, 4) that is then taken away, and
+	/// who presses jump after `wait` ticks of falling.
+	fn jump_after_ledge(wait: usize) -> f32 {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		map.set(5, 4, BRICK).unwrap();
+		let mut player = Player::new(vec![0; 4], &atlas, V2::new(5.0, 5.0));
+		let mut controller = PlatformerController::new(config());
+		step(&mut controller, &mut player, &map, false);
+		assert!(player.contacts.grounded);
+
+		map.set(5, 4, EMPTY_TILE).unwrap();
+		for _ in 0..wait {
+			step(&mut controller, &mut player, &map, false);
+			assert!(!player.contacts.grounded);
+		}
+		step(&mut controller, &mut player, &map, true);
+		player.vel.y
+	}
+
+	#[test]
+	fn jumps_just_after_leaving_a_ledge() {
+		assert!(jump_after_ledge(2) > 0.0);
+		assert!(jump_after_ledge(8) < 0.0);
+	}
+
+	/// Presses jump while falling from `height` and keeps it held, returning
+	/// the vertical speed a few ticks after landing.
+	fn jump_before_landing(height: f32) -> f32 {
+		let atlas = TileAtlas::new(16, 16);
+		let map = blank(&atlas);
+		let mut player = Player::new(vec![0; 4], &atlas, V2::new(5.0, height));
+		player.vel.y = -10.0;
+		let mut controller = PlatformerController::new(config());
+
+		let mut landed = None;
+		for tick in 0..100 {
+			step(&mut controller, &mut player, &map, true);
+			if landed.is_none() && player.contacts.grounded {
+				landed = Some(tick);
+			}
+			if landed.map_or(false, |landed| tick == landed + 1) {
+				return player.vel.y;
+			}
+		}
+		panic!("never landed");
+	}
+
+	#[test]
+	fn remembers_a_jump_pressed_before_landing() {
+		assert!(jump_before_landing(0.3) > 0.0);
+		assert!(jump_before_landing(4.0) <= 0.0);
+	}
+
+	#[test]
+	fn releasing_jump_early_cuts_it_short() {
+		let short = peak(3);
+		let full = peak(200);
+		assert!(short > 0.0);
+		assert!(short < full * 0.6, "short hop {} against full jump {}", short, full);
+	}
+
+	#[test]
+	fn built_in_configs_parse() {
+		PlatformerConfig::new();
+		TopDownConfig::new();
+		assert!(PlatformerConfig::parse(r#"{ "gravity": 60.0 }"#).is_err());
+	}
+
+	#[test]
+	fn rejects_out_of_range_values() {
+		let src = include_str!("../assets/player.json");
+		for &(from, to) in [("60.0", "-60.0"), ("60.0", "1e999"), ("\"jump_cut\": 0.5", "\"jump_cut\": 1.5")].iter() {
+			let broken = src.replacen(from, to, 1);
+			assert!(broken != src);
+			assert!(PlatformerConfig::parse(&broken).is_err(), "{}", broken);
+		}
+		assert!(TopDownConfig::parse(r#"{ "max_speed": 6.0, "acceleration": -1, "deceleration": 40.0 }"#).is_err());
+	}
+}
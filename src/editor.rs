@@ -0,0 +1,515 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use Scene;
+use SceneTrans;
+use autotile::AutoTiler;
+use gen::GridGenerator;
+use history::History;
+use keyboard;
+use keyboard::{Action, KeyState, MouseButton};
+use map::{Map, MapError};
+use render::Renderer;
+use save::SaveError;
+use stamp::{Stamp, StampLibrary};
+use tile::{Tile, TileAtlas};
+use utils::{line_cells, rerange, Rectangle};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tool {
+	Paint,
+	Line,
+	Rect,
+	Fill,
+	Entity,
+	Stamp,
+}
+
+impl Tool {
+	pub fn next(&self) -> Tool {
+		match *self {
+			Tool::Paint => Tool::Line,
+			Tool::Line => Tool::Rect,
+			Tool::Rect => Tool::Fill,
+			Tool::Fill => Tool::Entity,
+			Tool::Entity => Tool::Stamp,
+			Tool::Stamp => Tool::Paint,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Tool::Paint => "paint",
+			Tool::Line => "line",
+			Tool::Rect => "rect",
+			Tool::Fill => "fill",
+			Tool::Entity => "entity",
+			Tool::Stamp => "stamp",
+		}
+	}
+}
+
+/// Cells of the filled rectangle with corners `a` and `b`.
+pub fn rect_cells(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+	let mut cells = Vec::new();
+	for y in a.1.min(b.1)..(a.1.max(b.1) + 1) {
+		for x in a.0.min(b.0)..(a.0.max(b.0) + 1) {
+			cells.push((x, y));
+		}
+	}
+	cells
+}
+
+/// Cells 4-connected to `start` on `layer` that share its atlas id.
+pub fn flood_cells(map: &Map, layer: usize, start: (i32, i32)) -> Vec<(i32, i32)> {
+	let start = match map.resolve_write(start.0, start.1) {
+		Ok(start) => start,
+		Err(_) => { return Vec::new(); },
+	};
+	let target = match map.get_layer(layer, start.0, start.1) {
+		Some(tile) => tile.tex_id,
+		None => { return Vec::new(); },
+	};
+
+	let mut cells = Vec::new();
+	let mut seen = HashSet::new();
+	let mut stack = vec![start];
+	seen.insert(start);
+
+	while let Some((x, y)) = stack.pop() {
+		cells.push((x, y));
+		for &(nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+			// Canonical coordinates, so a wrapping map doesn't flood forever.
+			let (nx, ny) = match map.resolve_write(nx, ny) {
+				Ok(cell) => cell,
+				Err(_) => { continue; },
+			};
+			if seen.contains(&(nx, ny)) {
+				continue;
+			}
+			if map.get_layer(layer, nx, ny).map_or(false, |tile| tile.tex_id == target) {
+				seen.insert((nx, ny));
+				stack.push((nx, ny));
+			}
+		}
+	}
+	cells
+}
+
+/// True for a load that failed only because there is no file yet.
+fn is_not_found(e: &SaveError) -> bool {
+	match *e {
+		SaveError::Io(ref e) => e.kind() == io::ErrorKind::NotFound,
+		_ => false,
+	}
+}
+
+pub struct Editor<'a> {
+	pub map: Map<'a>,
+	pub atlas: &'a TileAtlas,
+	pub tool: Tool,
+	pub selected: u32,
+	pub layer: usize,
+	pub ratio: f32,
+	pub window: (f32, f32),
+	pub path: String,
+	/// Set when the file at `path` exists but couldn't be read, so saving
+	/// doesn't replace it with whatever the editor fell back to.
+	pub locked: bool,
+	/// Set for `Editor::scratch`, which never saves.
+	pub scratch: bool,
+	pub cursor: Option<(i32, i32)>,
+	pub history: History,
+	pub stamps: StampLibrary,
+	pub stamps_path: String,
+	/// Like `locked`, for the stamp library.
+	pub stamps_locked: bool,
+	/// Outcome of the last load or save, shown in the status line.
+	pub message: Option<String>,
+	/// Stamp being pasted, None while a new one is being captured.
+	pub stamp: Option<Stamp>,
+	anchor: Option<(i32, i32)>,
+	last_painted: Option<(i32, i32)>,
+	held: Vec<Action>,
+	mouse_left: KeyState,
+	mouse_right: KeyState,
+}
+
+impl<'a> Editor<'a> {
+	/// Opens the map saved at `path`, or a fresh grid map if there is none yet.
+	/// A map or stamp library that exists but can't be read is reported in the
+	/// status line and left alone on save.
+	pub fn new(atlas: &'a TileAtlas, ratio: f32, window_width: f32, window_height: f32, path: &str, stamps_path: &str) -> Editor<'a> {
+		let mut problems = Vec::new();
+
+		let (map, locked) = match Map::load(path, 20.0, ratio, atlas) {
+			Ok(map) => (map, false),
+			Err(e) => {
+				let locked = !is_not_found(&e);
+				if locked {
+					problems.push(format!("could not load {}: {}", path, e));
+				}
+				(Map::generate(101, 101, &GridGenerator::new(), 0, 20.0, ratio, atlas), locked)
+			},
+		};
+
+		let (stamps, stamps_locked) = match StampLibrary::load(stamps_path) {
+			Ok(stamps) => (stamps, false),
+			Err(e) => {
+				let locked = !is_not_found(&e);
+				if locked {
+					problems.push(format!("could not load {}: {}", stamps_path, e));
+				}
+				(StampLibrary::new(), locked)
+			},
+		};
+
+		let mut editor = Editor::with_map(atlas, map, stamps, ratio, (window_width, window_height), path, stamps_path);
+		editor.locked = locked;
+		editor.stamps_locked = stamps_locked;
+		editor.message = if problems.is_empty() { None } else { Some(problems.join("; ")) };
+		editor
+	}
+
+	/// A fresh grid map and no stamps, neither read from nor saved to disk, so
+	/// a recorded run and its replay start the editor from the same place.
+	pub fn scratch(atlas: &'a TileAtlas, ratio: f32, window_width: f32, window_height: f32) -> Editor<'a> {
+		let map = Map::generate(101, 101, &GridGenerator::new(), 0, 20.0, ratio, atlas);
+		let mut editor = Editor::with_map(atlas, map, StampLibrary::new(), ratio, (window_width, window_height), "", "");
+		editor.scratch = true;
+		editor
+	}
+
+	fn with_map(atlas: &'a TileAtlas, mut map: Map<'a>, stamps: StampLibrary, ratio: f32, window: (f32, f32), path: &str, stamps_path: &str) -> Editor<'a> {
+		if let Some(autotiler) = AutoTiler::for_atlas(atlas) {
+			map.set_autotiler(autotiler);
+		}
+		let ground = map.ground;
+
+		Editor {
+			map: map,
+			atlas: atlas,
+			tool: Tool::Paint,
+			selected: 0,
+			layer: ground,
+			ratio: ratio,
+			window: window,
+			path: String::from(path),
+			locked: false,
+			scratch: false,
+			cursor: None,
+			history: History::new(256),
+			stamps: stamps,
+			stamps_path: String::from(stamps_path),
+			stamps_locked: false,
+			message: None,
+			stamp: None,
+			anchor: None,
+			last_painted: None,
+			held: Vec::new(),
+			mouse_left: KeyState::Released,
+			mouse_right: KeyState::Released,
+		}
+	}
+
+	/// Saves the map and stamp library, reporting how it went in `message`.
+	pub fn save(&mut self) {
+		if self.scratch {
+			self.message = Some(String::from("nothing is saved while recording or replaying"));
+			return;
+		}
+
+		let map = if self.locked {
+			format!("kept unreadable {}", self.path)
+		} else {
+			match self.map.save(Path::new(&self.path)) {
+				Ok(()) => format!("saved {}", self.path),
+				Err(e) => format!("could not save {}: {}", self.path, e),
+			}
+		};
+
+		let stamps = if self.stamps_locked {
+			format!("kept unreadable {}", self.stamps_path)
+		} else {
+			match self.stamps.save(Path::new(&self.stamps_path)) {
+				Ok(()) => format!("saved {}", self.stamps_path),
+				Err(e) => format!("could not save {}: {}", self.stamps_path, e),
+			}
+		};
+
+		self.message = Some(format!("{}; {}", map, stamps));
+	}
+
+	/// Selects the library stamp after the current one.
+	fn next_stamp(&mut self) {
+		if self.stamps.stamps.is_empty() {
+			return;
+		}
+		let next = match self.stamp {
+			Some(ref current) => self.stamps.stamps.iter().position(|stamp| stamp.name == current.name).map_or(0, |i| (i + 1) % self.stamps.stamps.len()),
+			None => 0,
+		};
+		self.stamp = Some(self.stamps.stamps[next].clone());
+	}
+
+	/// True on the first frame `action` is held down.
+	fn just_pressed(&self, inputs: &keyboard::Inputs, action: Action) -> bool {
+		inputs.is_pressed(action) && !self.held.contains(&action)
+	}
+
+	fn palette_height(&self) -> f32 {
+		0.075
+	}
+
+	fn palette_width(&self) -> f32 {
+		2.0 / (self.atlas.num_entries as f32)
+	}
+
+	/// Palette entry under a point in normalized device coordinates, if any.
+	fn palette_pick(&self, ndc_x: f32, ndc_y: f32) -> Option<u32> {
+		if ndc_y < 1.0 - self.palette_height() {
+			return None;
+		}
+		let index = ((ndc_x + 1.0) / self.palette_width()) as u32;
+		if index < self.atlas.num_entries { Some(index) } else { None }
+	}
+
+	/// Shows a failed edit in the status line.
+	fn report(&mut self, result: Result<(), MapError>) {
+		if let Err(e) = result {
+			self.message = Some(format!("edit failed: {}", e));
+		}
+	}
+
+	fn paint(&mut self, cells: Vec<(i32, i32)>, id: u32) {
+		let result = self.history.fill(&mut self.map, self.layer, &cells, id);
+		self.report(result);
+	}
+
+	fn place_entity(&mut self, cell: (i32, i32)) {
+		let result = self.history.add_entity(&mut self.map, cell.0 as f32, cell.1 as f32, self.selected);
+		self.report(result);
+	}
+
+	fn remove_entity(&mut self, cell: (i32, i32)) {
+		self.history.begin_group();
+		let area = Rectangle::new(cell.0 as f32, cell.1 as f32, 1.0, 1.0);
+		// Highest index first, so removing one doesn't shift the rest.
+		for index in self.map.entities_in(&area).into_iter().rev() {
+			let result = self.history.remove_entity(&mut self.map, index);
+			self.report(result);
+		}
+		self.history.end_group();
+	}
+
+	fn press(&mut self, cell: (i32, i32)) {
+		match self.tool {
+			Tool::Paint => {
+				let id = self.selected;
+				self.history.begin_group();
+				self.paint(vec![cell], id);
+				self.last_painted = Some(cell);
+			},
+			Tool::Line | Tool::Rect => { self.anchor = Some(cell); },
+			Tool::Stamp => {
+				let result = match self.stamp {
+					Some(ref stamp) => stamp.paste(&mut self.map, &mut self.history, cell.0, cell.1),
+					None => {
+						self.anchor = Some(cell);
+						Ok(())
+					},
+				};
+				self.report(result);
+			},
+			Tool::Fill => {
+				let cells = flood_cells(&self.map, self.layer, cell);
+				let id = self.selected;
+				self.paint(cells, id);
+			},
+			Tool::Entity => { self.place_entity(cell); },
+		}
+	}
+
+	fn drag(&mut self, cell: (i32, i32)) {
+		if self.tool == Tool::Paint {
+			let from = self.last_painted.unwrap_or(cell);
+			if from != cell {
+				let id = self.selected;
+				self.paint(line_cells(from, cell), id);
+			}
+			self.last_painted = Some(cell);
+		}
+	}
+
+	fn release(&mut self, cell: (i32, i32)) {
+		if let Some(anchor) = self.anchor.take() {
+			let id = self.selected;
+			match self.tool {
+				Tool::Line => self.paint(line_cells(anchor, cell), id),
+				Tool::Rect => self.paint(rect_cells(anchor, cell), id),
+				Tool::Stamp => {
+					let area = Rectangle::new(anchor.0.min(cell.0), anchor.1.min(cell.1), (anchor.0 - cell.0).abs() + 1, (anchor.1 - cell.1).abs() + 1);
+					let name = format!("stamp {}", self.stamps.stamps.len() + 1);
+					match Stamp::capture(&self.map, &name, &area) {
+						Ok(stamp) => {
+							self.stamps.insert(stamp.clone());
+							self.stamp = Some(stamp);
+						},
+						Err(e) => { self.message = Some(format!("could not capture stamp: {}", e)); },
+					}
+				},
+				_ => { },
+			}
+		}
+		if self.last_painted.take().is_some() {
+			self.history.end_group();
+		}
+	}
+
+	/// Right click picks the tile under the cursor, removes an entity with the
+	/// entity tool, or drops the current stamp to capture a new one.
+	fn pick(&mut self, cell: (i32, i32)) {
+		if self.tool == Tool::Entity {
+			self.remove_entity(cell);
+		} else if self.tool == Tool::Stamp {
+			self.stamp = None;
+		} else if let Some(tile) = self.map.get_layer(self.layer, cell.0, cell.1) {
+			if !tile.is_empty() {
+				self.selected = tile.tex_id;
+			}
+		}
+	}
+}
+
+impl<'a> Scene for Editor<'a> {
+	fn handle_input(&mut self, inputs: &keyboard::Inputs, coords: Option<(i32, i32)>, _clicked: Option<MouseButton>, dt: f32) -> SceneTrans {
+		let mut state = SceneTrans::Editor;
+
+		if inputs.is_pressed(Action::Back) { state = SceneTrans::Menu; }
+		if inputs.is_pressed(Action::Quit) { state = SceneTrans::Quit; }
+		if self.just_pressed(inputs, Action::Tool) {
+			self.tool = self.tool.next();
+			self.anchor = None;
+		}
+		if self.just_pressed(inputs, Action::Layer) {
+			self.layer = (self.layer + 1) % self.map.layers.len();
+		}
+		if self.just_pressed(inputs, Action::Save) {
+			self.save();
+		}
+		if self.just_pressed(inputs, Action::Undo) {
+			let result = self.history.undo(&mut self.map).map(|_| ());
+			self.report(result);
+		}
+		if self.just_pressed(inputs, Action::Redo) {
+			let result = self.history.redo(&mut self.map).map(|_| ());
+			self.report(result);
+		}
+		if self.just_pressed(inputs, Action::Next) {
+			self.next_stamp();
+		}
+		if self.just_pressed(inputs, Action::Rotate) {
+			self.stamp = self.stamp.as_ref().map(|stamp| stamp.rotated());
+		}
+		if self.just_pressed(inputs, Action::Mirror) {
+			self.stamp = self.stamp.as_ref().map(|stamp| stamp.mirrored_x());
+		}
+
+		let pan = 8.0 * dt;
+		if inputs.is_pressed(Action::Left) { self.map.view.x -= pan; }
+		if inputs.is_pressed(Action::Right) { self.map.view.x += pan; }
+		if inputs.is_pressed(Action::Down) { self.map.view.y -= pan; }
+		if inputs.is_pressed(Action::Up) { self.map.view.y += pan; }
+		self.map.stream();
+
+		let pressed_left = inputs.mouse_left == KeyState::Pressed && self.mouse_left == KeyState::Released;
+		let released_left = inputs.mouse_left == KeyState::Released && self.mouse_left == KeyState::Pressed;
+
+		self.cursor = None;
+		if let Some(coords) = coords {
+			let x = rerange(coords.0 as f32, 0.0, self.window.0, -1.0, 1.0);
+			let y = -rerange(coords.1 as f32, 0.0, self.window.1, -1.0, 1.0);
+
+			let pressed_right = inputs.mouse_right == KeyState::Pressed && self.mouse_right == KeyState::Released;
+
+			if let Some(id) = self.palette_pick(x, y) {
+				if pressed_left {
+					self.selected = id;
+				}
+			} else {
+				let (view_x, view_y) = self.map.view.pick(x, y);
+				let cell = ((self.map.view.x + view_x).floor() as i32, (self.map.view.y + view_y).floor() as i32);
+				self.cursor = Some(cell);
+
+				if pressed_left {
+					self.press(cell);
+				} else if released_left {
+					self.release(cell);
+				} else if inputs.mouse_left == KeyState::Pressed {
+					self.drag(cell);
+				}
+
+				if pressed_right {
+					self.pick(cell);
+				}
+			}
+		}
+
+		// A stroke ends however the button comes up, even over the palette or
+		// outside the window, where `release` never sees it.
+		if released_left {
+			self.anchor = None;
+			self.last_painted = None;
+			self.history.end_group();
+		}
+
+		self.mouse_left = inputs.mouse_left;
+		self.mouse_right = inputs.mouse_right;
+		self.held = inputs.keys.iter().filter(|key| *key.1 == KeyState::Pressed).map(|key| *key.0).collect();
+		return state;
+	}
+
+	fn draw(&mut self, renderer: &mut Renderer, _alpha: f32) {
+		self.map.draw(renderer, None, 1.0);
+
+		if let Some(cursor) = self.cursor {
+			let x = (cursor.0 as f32) - self.map.view.x;
+			let y = (cursor.1 as f32) - self.map.view.y;
+			let matrix = self.map.view.matrix(x, y);
+			Tile::new(self.selected, self.atlas).draw(renderer, matrix, 0.5);
+		}
+
+		let width = self.palette_width();
+		let height = self.palette_height();
+		for id in 0..self.atlas.num_entries {
+			let matrix = [
+				[width / 2.0, 0.0, 0.0, 0.0],
+				[0.0, height / 2.0, 0.0, 0.0],
+				[0.0, 0.0, 1.0, 0.0],
+				[-1.0 + width * (id as f32) + width / 2.0, 1.0 - height / 2.0, 0.0, 1.0f32],
+			];
+			let opacity = if id == self.selected { 1.0 } else { 0.6 };
+			Tile::new(id, self.atlas).draw(renderer, matrix, opacity);
+		}
+
+		let status_matrix = [
+			[0.05 / self.ratio, 0.0, 0.0, 0.0],
+			[0.0, 0.05, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[-1.0, -0.99, 0.0, 1.0],
+		];
+
+		let mut status = format!("{} | layer: {} | tile: {}", self.tool.name(), self.map.layers[self.layer].name, self.selected);
+		if self.tool == Tool::Stamp {
+			status = match self.stamp {
+				Some(ref stamp) => format!("{} | {} ({}x{})", status, stamp.name, stamp.width, stamp.height),
+				None => format!("{} | drag to capture", status),
+			};
+		}
+		if let Some(ref message) = self.message {
+			status = format!("{} | {}", status, message);
+		}
+		renderer.draw_text(status.as_str(), status_matrix, (1.0, 1.0, 0.0, 1.0));
+	}
+}
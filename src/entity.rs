@@ -1,11 +1,12 @@
-use glium;
-use glium::Surface;
-
+use render::{Matrix, Renderer};
 use tile::TileAtlas;
 
 pub struct Entity<'a> {
     pub x: f32,
     pub y: f32,
+    /// Position at the start of the current tick, for interpolated drawing.
+    pub prev_x: f32,
+    pub prev_y: f32,
     pub tex_id: u32,
     pub atlas: &'a TileAtlas,
 }
@@ -15,20 +16,19 @@ impl<'a> Entity<'a> {
         Entity {
             x: x,
             y: y,
+            prev_x: x,
+            prev_y: y,
             tex_id: id,
             atlas: atlas,
         }
     }
 
-    pub fn draw(&self, mut target: &mut glium::Frame, program: &glium::Program, matrix: [[f32; 4]; 4]) {
-        let buffer = self.atlas.tex_verts.get(self.tex_id as usize).unwrap();
-        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-
-        let entity_uniform = uniform! {
-            matrix: matrix,
-            tex: self.atlas.texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-        };
+    /// Position `alpha` of the way from the start of the tick to now.
+    pub fn lerp(&self, alpha: f32) -> (f32, f32) {
+        (self.prev_x + (self.x - self.prev_x) * alpha, self.prev_y + (self.y - self.prev_y) * alpha)
+    }
 
-        target.draw(buffer, &indices, program, &entity_uniform, &Default::default()).unwrap();
+    pub fn draw(&self, renderer: &mut Renderer, matrix: Matrix) {
+        renderer.draw_sprite(self.atlas, self.atlas.frame(self.tex_id), matrix, 1.0);
     }
 }
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use fov::Visibility;
+use map::Map;
+
+/// Opacity of the black drawn over cells that were seen before but aren't now.
+pub const EXPLORED_SHADE: f32 = 0.5;
+
+/// How much of a cell the player can make out.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sight {
+	Unexplored,
+	Explored,
+	Visible,
+}
+
+/// Fog of war: the cells seen from the last `Map::reveal`, and every cell
+/// ever seen.
+pub struct Fog {
+	/// Cells ever seen, in the coordinates the map's edge policy resolves them to.
+	pub explored: HashSet<(i32, i32)>,
+	pub visible: Visibility,
+}
+
+impl Fog {
+	pub fn new() -> Fog {
+		Fog {
+			explored: HashSet::new(),
+			visible: HashSet::new(),
+		}
+	}
+}
+
+impl<'a> Map<'a> {
+	/// Turns on fog of war with nothing explored yet.
+	pub fn enable_fog(&mut self) {
+		self.fog = Some(Fog::new());
+	}
+
+	/// Recomputes what can be seen from `origin` and adds it to the explored
+	/// cells. Does nothing without fog of war.
+	pub fn reveal(&mut self, origin: (i32, i32), radius: i32) {
+		if self.fog.is_none() {
+			return;
+		}
+
+		let visible = self.field_of_view(origin, radius);
+		let explored: Vec<(i32, i32)> = visible.iter().filter_map(|&(x, y)| self.resolve(x, y)).collect();
+
+		let fog = self.fog.as_mut().unwrap();
+		fog.explored.extend(explored);
+		fog.visible = visible;
+	}
+
+	/// How visible the cell at (x, y) is. Everything is visible without fog of war.
+	pub fn sight(&self, x: i32, y: i32) -> Sight {
+		let fog = match self.fog {
+			Some(ref fog) => fog,
+			None => { return Sight::Visible; },
+		};
+
+		if fog.visible.contains(&(x, y)) {
+			Sight::Visible
+		} else if self.resolve(x, y).map_or(false, |cell| fog.explored.contains(&cell)) {
+			Sight::Explored
+		} else {
+			Sight::Unexplored
+		}
+	}
+}
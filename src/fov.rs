@@ -0,0 +1,194 @@
+//! Field of view by symmetric shadowcasting.
+//!
+//! The map is scanned one quadrant at a time, row by row away from the
+//! origin, narrowing the visible arc of each row by the opaque tiles of the
+//! row before. A floor tile is only seen when its centre lies inside the arc,
+//! which makes sight symmetric: if a sees b, b sees a. Opaque tiles are seen
+//! whenever any part of them is, so walls light up around what is visible.
+//! Off the edge of a map that doesn't wrap is opaque and never seen.
+
+use std::collections::HashSet;
+
+use chunk::floor_div;
+use map::Map;
+
+/// Cells visible from some origin, in world coordinates.
+pub type Visibility = HashSet<(i32, i32)>;
+
+/// An exact slope `num / den` with `den` positive, so cells lying exactly on
+/// an arc's edge are not lost to rounding.
+#[derive(Clone, Copy)]
+struct Slope {
+	num: i32,
+	den: i32,
+}
+
+impl Slope {
+	fn new(num: i32, den: i32) -> Slope {
+		Slope {
+			num: num,
+			den: den,
+		}
+	}
+
+	/// Slope from the origin to the near edge of the cell `col` in row `depth`.
+	fn edge(depth: i32, col: i32) -> Slope {
+		Slope::new(2 * col - 1, 2 * depth)
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Quadrant {
+	North,
+	East,
+	South,
+	West,
+}
+
+impl Quadrant {
+	fn cell(&self, origin: (i32, i32), depth: i32, col: i32) -> (i32, i32) {
+		match *self {
+			Quadrant::North => (origin.0 + col, origin.1 + depth),
+			Quadrant::South => (origin.0 + col, origin.1 - depth),
+			Quadrant::East => (origin.0 + depth, origin.1 + col),
+			Quadrant::West => (origin.0 - depth, origin.1 + col),
+		}
+	}
+}
+
+struct Caster<'m, 'a: 'm> {
+	map: &'m Map<'a>,
+	origin: (i32, i32),
+	radius: i32,
+	visible: Visibility,
+}
+
+impl<'m, 'a: 'm> Caster<'m, 'a> {
+	fn scan(&mut self, quadrant: Quadrant, depth: i32, mut start: Slope, end: Slope) {
+		if depth > self.radius {
+			return;
+		}
+
+		// Columns whose centres round into the arc, ties rounding inwards.
+		let min_col = floor_div(2 * depth * start.num + start.den, 2 * start.den);
+		let max_col = -floor_div(-(2 * depth * end.num - end.den), 2 * end.den);
+
+		let mut prev_wall = None;
+		for col in min_col..(max_col + 1) {
+			let cell = quadrant.cell(self.origin, depth, col);
+			let wall = self.map.blocks_sight(cell.0, cell.1);
+			let symmetric = col * start.den >= depth * start.num && col * end.den <= depth * end.num;
+
+			let on_map = self.map.resolve_write(cell.0, cell.1).is_ok();
+			if (wall || symmetric) && on_map && col * col + depth * depth <= self.radius * self.radius {
+				self.visible.insert(cell);
+			}
+
+			match prev_wall {
+				Some(true) if !wall => { start = Slope::edge(depth, col); },
+				Some(false) if wall => { self.scan(quadrant, depth + 1, start, Slope::edge(depth, col)); },
+				_ => (),
+			}
+			prev_wall = Some(wall);
+		}
+
+		if prev_wall == Some(false) {
+			self.scan(quadrant, depth + 1, start, end);
+		}
+	}
+}
+
+/// True if `field_of_view(a, ..)` would include `b`, scanning only the
+/// quadrants `b` lies in.
+fn sees(map: &Map, a: (i32, i32), b: (i32, i32)) -> bool {
+	let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+	let radius = ((dx * dx + dy * dy) as f64).sqrt().ceil() as i32;
+	let mut caster = Caster {
+		map: map,
+		origin: a,
+		radius: radius,
+		visible: HashSet::new(),
+	};
+
+	let quadrants = [
+		(Quadrant::North, dy >= dx.abs()),
+		(Quadrant::East, dx >= dy.abs()),
+		(Quadrant::South, -dy >= dx.abs()),
+		(Quadrant::West, -dx >= dy.abs()),
+	];
+	for &(quadrant, holds_b) in quadrants.iter() {
+		if holds_b {
+			caster.scan(quadrant, 1, Slope::new(-1, 1), Slope::new(1, 1));
+		}
+	}
+	caster.visible.contains(&b)
+}
+
+impl<'a> Map<'a> {
+	/// Cells visible from `origin` within `radius` tiles, the origin included.
+	pub fn field_of_view(&self, origin: (i32, i32), radius: i32) -> Visibility {
+		let mut caster = Caster {
+			map: self,
+			origin: origin,
+			radius: radius,
+			visible: HashSet::new(),
+		};
+		caster.visible.insert(origin);
+
+		for quadrant in [Quadrant::North, Quadrant::East, Quadrant::South, Quadrant::West].iter() {
+			caster.scan(*quadrant, 1, Slope::new(-1, 1), Slope::new(1, 1));
+		}
+		caster.visible
+	}
+
+	/// True if `b` is in the field of view of `a` given a large enough radius,
+	/// or the other way round. The ends themselves may be opaque, and
+	/// `line_of_sight(a, b) == line_of_sight(b, a)`.
+	pub fn line_of_sight(&self, a: (i32, i32), b: (i32, i32)) -> bool {
+		a == b || sees(self, a, b) || sees(self, b, a)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use gen::GridGenerator;
+	use map::{EdgePolicy, Map};
+	use tile::TileAtlas;
+
+	const WALL: u32 = 11;
+
+	fn map<'a>(atlas: &'a TileAtlas) -> Map<'a> {
+		let mut map = Map::generate(9, 9, &GridGenerator::new(), 0, 10.0, 1.0, atlas);
+		for &(x, y) in [(4, 4), (4, 5), (2, 6), (6, 1), (7, 1)].iter() {
+			map.set(x, y, WALL).unwrap();
+		}
+		map
+	}
+
+	#[test]
+	fn sees_nothing_off_the_map() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		for &edge in [EdgePolicy::Void, EdgePolicy::Clamp].iter() {
+			map.set_edge(edge, 0);
+			let visible = map.field_of_view((0, 0), 6);
+			assert!(visible.iter().all(|&(x, y)| x >= 0 && y >= 0 && x < 9 && y < 9));
+			assert!(!map.line_of_sight((0, 0), (-3, -3)));
+		}
+	}
+
+	#[test]
+	fn line_of_sight_matches_the_field_of_view() {
+		let atlas = TileAtlas::new(16, 16);
+		let map = map(&atlas);
+		let floors: Vec<(i32, i32)> = (0..81).map(|i| (i % 9, i / 9)).filter(|&(x, y)| !map.blocks_sight(x, y)).collect();
+
+		for &a in floors.iter() {
+			let visible = map.field_of_view(a, 20);
+			for &b in floors.iter() {
+				assert_eq!(map.line_of_sight(a, b), visible.contains(&b), "{:?} to {:?}", a, b);
+				assert_eq!(map.line_of_sight(a, b), map.line_of_sight(b, a));
+			}
+		}
+	}
+}
@@ -1,25 +1,39 @@
-use glium;
-use glium_text;
-use glium::Surface;
-
 use Scene;
 use SceneTrans;
+use autotile::AutoTiler;
 use player::Player;
+use controller::{ControlInput, Controller};
+use entity::Entity;
 use map::Map;
+use gen::MapGenerator;
+use render::Renderer;
 use tile::TileAtlas;
+use replay::Checksum;
 use utils::V2;
 use keyboard;
+use keyboard::MouseButton;
+
+/// How many tiles the player can see.
+pub const SIGHT_RADIUS: i32 = 8;
 
 pub struct Game<'a> {
     pub player: Player<'a>,
     pub map: Map<'a>,
+    /// Starts as the map's control style; swap it to play the map differently.
+    pub controller: Box<Controller>,
     pub score: u32,
     pub ratio: f32,
 }
 
 impl<'a> Game<'a> {
-    pub fn new(ratio: f32, atlas: &'a TileAtlas) -> Game {
-        let map = Map::new(101, 101, 20.0, ratio, &atlas);
+    pub fn new(ratio: f32, atlas: &'a TileAtlas, generator: &MapGenerator, seed: u64) -> Game<'a> {
+        let mut map = Map::generate(101, 101, generator, seed, 20.0, ratio, &atlas);
+        map.add_entity(Entity::new(1.0, 1.0, 14, atlas));
+        if let Some(autotiler) = AutoTiler::for_atlas(atlas) {
+            map.set_autotiler(autotiler);
+        }
+        map.enable_fog();
+        let controller = map.style.controller();
 
         let dirs = vec![1, 0, 4, 5];
         let player = Player::new(dirs, &atlas, V2::new(0.0, 0.0));
@@ -28,43 +42,55 @@ impl<'a> Game<'a> {
         Game {
             player: player,
             map: map,
+            controller: controller,
             score: score,
             ratio: ratio,
         }
     }
+
+    /// Hash of the simulated game state, for spotting replays that drift from
+    /// their recording. The menu and editor are not covered.
+    pub fn checksum(&self) -> u64 {
+        let mut sum = Checksum::new();
+        sum.write_f32(self.player.pos.x);
+        sum.write_f32(self.player.pos.y);
+        sum.write_f32(self.player.vel.x);
+        sum.write_f32(self.player.vel.y);
+        sum.write_u32(self.score);
+        self.controller.checksum(&mut sum);
+        for entity in self.map.entities().iter() {
+            sum.write_f32(entity.x);
+            sum.write_f32(entity.y);
+            sum.write_u32(entity.tex_id);
+        }
+        self.map.checksum(&mut sum);
+        sum.finish()
+    }
 }
 
-impl<'a> Scene<'a> for Game<'a> {
-    fn handle_input(&mut self, inputs: &keyboard::Inputs, coords: Option<(i32, i32)>, clicked: Option<glium::glutin::MouseButton>, dt: f32) -> SceneTrans {
+impl<'a> Scene for Game<'a> {
+    fn handle_input(&mut self, inputs: &keyboard::Inputs, coords: Option<(i32, i32)>, clicked: Option<MouseButton>, dt: f32) -> SceneTrans {
         let mut state = SceneTrans::Game;
-        let mut player_inputs = Vec::new();
-        if inputs.has_update() {
-            for key in inputs.keys.iter() {
-                if *key.1 == keyboard::KeyState::Pressed {
-                    match *key.0 {
-                        keyboard::Action::Up => { player_inputs.push(key.0); },
-                        keyboard::Action::Down => { player_inputs.push(key.0); },
-                        keyboard::Action::Left => { player_inputs.push(key.0); },
-                        keyboard::Action::Right => { player_inputs.push(key.0); },
-                        keyboard::Action::Space => { player_inputs.push(key.0); },
-                        keyboard::Action::Back => { state =  SceneTrans::Menu; },
-                        keyboard::Action::Quit => { state =  SceneTrans::Quit; }
-                        _ => { },
-                    }
-                }
-            }
+        if inputs.is_pressed(keyboard::Action::Back) {
+            state = SceneTrans::Menu;
         }
-        else {
-            self.player.move_to(&self.map, 0.0, -1.0, dt);
+        if inputs.is_pressed(keyboard::Action::Quit) {
+            state = SceneTrans::Quit;
         }
 
-        self.player.handle_input(&self.map, player_inputs, dt);
+        self.player.begin_tick();
+        self.map.begin_tick();
+        self.controller.update(&mut self.player, &self.map, &ControlInput::from_inputs(inputs), dt);
+        self.map.stream();
+
+        let centre = (self.player.pos.x + self.player.size.x * 0.5, self.player.pos.y + self.player.size.y * 0.5);
+        let cell = (centre.0.floor() as i32, centre.1.floor() as i32);
+        self.map.reveal(cell, SIGHT_RADIUS);
         return state;
     }
 
-    fn draw(&mut self, mut target: &mut glium::Frame, program: &glium::Program, text_system: &glium_text::TextSystem, font: &glium_text::FontTexture) {
-        self.map.draw(&mut target, &program);
-        self.player.draw(target, &program, &self.map.view);
+    fn draw(&mut self, renderer: &mut Renderer, alpha: f32) {
+        self.map.draw(renderer, Some(&mut self.player), alpha);
 
     	let score_matrix = [
     		[0.05 / self.ratio, 0.0, 0.0, 0.0],
@@ -80,9 +106,7 @@ impl<'a> Scene<'a> for Game<'a> {
     		[-1.0, -0.99, 0.0, 1.0],
     	];
 
-        let title_text = glium_text::TextDisplay::new(text_system, font, "TilePaste");
-        let score_text = glium_text::TextDisplay::new(text_system, font, format!("score: {}", self.score).as_str());
-        glium_text::draw(&score_text, text_system, target, score_matrix, (1.0, 1.0, 0.0, 1.0));
-        glium_text::draw(&title_text, text_system, target, title_matrix, (1.0, 1.0, 0.0, 1.0));
+        renderer.draw_text(format!("score: {}", self.score).as_str(), score_matrix, (1.0, 1.0, 0.0, 1.0));
+        renderer.draw_text("TilePaste", title_matrix, (1.0, 1.0, 0.0, 1.0));
     }
 }
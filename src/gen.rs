@@ -0,0 +1,384 @@
+use std::cmp::{max, min};
+
+use chunk::{ChunkSource, CHUNK_SIZE};
+use utils::{translate, Rectangle};
+
+/// Builds the ground layer of a map. The same seed always yields the same map.
+pub trait MapGenerator {
+	/// Atlas ids for a `width` by `height` map, row-major with row 0 at the bottom.
+	fn generate(&self, width: i32, height: i32, seed: u64) -> Vec<u32>;
+}
+
+/// xorshift64* generator, small and stable across platforms so seeds stay reproducible.
+pub struct Rng {
+	state: u64,
+}
+
+impl Rng {
+	pub fn new(seed: u64) -> Rng {
+		Rng {
+			state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed },
+		}
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.state ^= self.state >> 12;
+		self.state ^= self.state << 25;
+		self.state ^= self.state >> 27;
+		self.state.wrapping_mul(0x2545F4914F6CDD1D)
+	}
+
+	/// Uniform float in [0, 1).
+	pub fn next_f32(&mut self) -> f32 {
+		((self.next_u64() >> 40) as f32) / ((1u64 << 24) as f32)
+	}
+
+	/// Uniform integer in [min, max).
+	pub fn range(&mut self, min: i32, max: i32) -> i32 {
+		if max <= min {
+			return min;
+		}
+		min + (self.next_u64() % ((max - min) as u64)) as i32
+	}
+}
+
+/// Lines of one tile every `spacing` rows and columns over a fill tile.
+pub struct GridGenerator {
+	pub spacing: i32,
+	pub line: u32,
+	pub fill: u32,
+}
+
+impl GridGenerator {
+	pub fn new() -> GridGenerator {
+		GridGenerator {
+			spacing: 5,
+			line: 10,
+			fill: 9,
+		}
+	}
+}
+
+impl MapGenerator for GridGenerator {
+	fn generate(&self, width: i32, height: i32, _seed: u64) -> Vec<u32> {
+		let mut ids = Vec::with_capacity((width * height) as usize);
+		for index in 0..(width * height) {
+			let x = index % width;
+			let y = index / width;
+			if (x % self.spacing) == 0 || (y % self.spacing) == 0 {
+				ids.push(self.line);
+			} else {
+				ids.push(self.fill);
+			}
+		}
+		ids
+	}
+}
+
+fn hash(x: i32, y: i32, seed: u64) -> f32 {
+	let mut h = seed ^ ((x as u32 as u64).wrapping_mul(0x9E3779B97F4A7C15)) ^ ((y as u32 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F));
+	h ^= h >> 33;
+	h = h.wrapping_mul(0xFF51AFD7ED558CCD);
+	h ^= h >> 33;
+	h = h.wrapping_mul(0xC4CEB9FE1A85EC53);
+	h ^= h >> 33;
+	((h >> 40) as f32) / ((1u64 << 24) as f32)
+}
+
+fn smooth(t: f32) -> f32 {
+	t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
+
+/// Smoothly interpolated value noise in [0, 1).
+pub fn value_noise(x: f32, y: f32, seed: u64) -> f32 {
+	let x0 = x.floor();
+	let y0 = y.floor();
+	let tx = smooth(x - x0);
+	let ty = smooth(y - y0);
+	let x0 = x0 as i32;
+	let y0 = y0 as i32;
+
+	let bottom = lerp(hash(x0, y0, seed), hash(x0 + 1, y0, seed), tx);
+	let top = lerp(hash(x0, y0 + 1, seed), hash(x0 + 1, y0 + 1, seed), tx);
+	lerp(bottom, top, ty)
+}
+
+/// Terrain from octaves of value noise. `bands` maps noise heights to tiles:
+/// each entry is an upper bound and the id used below it, in ascending order.
+pub struct NoiseTerrain {
+	pub scale: f32,
+	pub octaves: u32,
+	pub bands: Vec<(f32, u32)>,
+}
+
+impl NoiseTerrain {
+	pub fn new() -> NoiseTerrain {
+		NoiseTerrain {
+			scale: 16.0,
+			octaves: 4,
+			bands: vec![(0.4, 9), (0.65, 10), (1.0, 11)],
+		}
+	}
+
+	pub fn height(&self, x: i32, y: i32, seed: u64) -> f32 {
+		let mut total = 0.0;
+		let mut amplitude = 1.0;
+		let mut frequency = 1.0 / self.scale;
+		let mut norm = 0.0;
+
+		for octave in 0..self.octaves {
+			let octave_seed = seed.wrapping_add(octave as u64);
+			total += value_noise((x as f32) * frequency, (y as f32) * frequency, octave_seed) * amplitude;
+			norm += amplitude;
+			amplitude *= 0.5;
+			frequency *= 2.0;
+		}
+
+		total / norm
+	}
+
+	pub fn sample(&self, x: i32, y: i32, seed: u64) -> u32 {
+		let height = self.height(x, y, seed);
+		for &(bound, id) in self.bands.iter() {
+			if height < bound {
+				return id;
+			}
+		}
+		self.bands.last().map_or(0, |band| band.1)
+	}
+}
+
+impl MapGenerator for NoiseTerrain {
+	fn generate(&self, width: i32, height: i32, seed: u64) -> Vec<u32> {
+		let mut ids = Vec::with_capacity((width * height) as usize);
+		for y in 0..height {
+			for x in 0..width {
+				ids.push(self.sample(x, y, seed));
+			}
+		}
+		ids
+	}
+}
+
+/// Feeds a streaming map's ground layer from `NoiseTerrain`, which needs no
+/// neighbouring chunks and so can be sampled anywhere.
+pub struct NoiseChunks {
+	pub terrain: NoiseTerrain,
+	pub seed: u64,
+	pub layer: usize,
+}
+
+impl ChunkSource for NoiseChunks {
+	fn load(&mut self, layer: usize, chunk_x: i32, chunk_y: i32) -> Option<Vec<u32>> {
+		if layer != self.layer {
+			return None;
+		}
+
+		let mut ids = Vec::with_capacity((CHUNK_SIZE * CHUNK_SIZE) as usize);
+		for y in 0..CHUNK_SIZE {
+			for x in 0..CHUNK_SIZE {
+				ids.push(self.terrain.sample(chunk_x * CHUNK_SIZE + x, chunk_y * CHUNK_SIZE + y, self.seed));
+			}
+		}
+		Some(ids)
+	}
+}
+
+/// Caves grown by a cellular automaton from random noise: a cell becomes a
+/// wall with at least five walls around it and stays open otherwise.
+pub struct CaveGenerator {
+	pub fill_chance: f32,
+	pub steps: u32,
+	pub wall: u32,
+	pub floor: u32,
+}
+
+impl CaveGenerator {
+	pub fn new() -> CaveGenerator {
+		CaveGenerator {
+			fill_chance: 0.45,
+			steps: 5,
+			wall: 11,
+			floor: 9,
+		}
+	}
+}
+
+impl MapGenerator for CaveGenerator {
+	fn generate(&self, width: i32, height: i32, seed: u64) -> Vec<u32> {
+		let mut rng = Rng::new(seed);
+		let mut walls = Vec::with_capacity((width * height) as usize);
+		for y in 0..height {
+			for x in 0..width {
+				let edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+				walls.push(edge || rng.next_f32() < self.fill_chance);
+			}
+		}
+
+		for _ in 0..self.steps {
+			let mut next = walls.clone();
+			for y in 0..height {
+				for x in 0..width {
+					let mut count = 0;
+					for dy in -1..2 {
+						for dx in -1..2 {
+							let nx = x + dx;
+							let ny = y + dy;
+							if nx < 0 || ny < 0 || nx >= width || ny >= height || walls[translate(nx, ny, width)] {
+								count += 1;
+							}
+						}
+					}
+					next[translate(x, y, width)] = count >= 5;
+				}
+			}
+			walls = next;
+		}
+
+		walls.iter().map(|wall| if *wall { self.wall } else { self.floor }).collect()
+	}
+}
+
+/// Rooms placed in the leaves of a binary space partition, joined by
+/// L-shaped corridors between the centres of sibling partitions.
+pub struct BspDungeon {
+	/// Shortest side a partition may be split down to, at least 2.
+	pub min_size: i32,
+	pub max_depth: u32,
+	pub wall: u32,
+	pub floor: u32,
+}
+
+impl BspDungeon {
+	pub fn new() -> BspDungeon {
+		BspDungeon {
+			min_size: 8,
+			max_depth: 5,
+			wall: 11,
+			floor: 9,
+		}
+	}
+
+	fn carve(&self, ids: &mut Vec<u32>, width: i32, x: i32, y: i32) {
+		ids[translate(x, y, width)] = self.floor;
+	}
+
+	fn corridor(&self, ids: &mut Vec<u32>, width: i32, from: (i32, i32), to: (i32, i32)) {
+		let (mut x, mut y) = from;
+		while x != to.0 {
+			self.carve(ids, width, x, y);
+			x += if to.0 > x { 1 } else { -1 };
+		}
+		while y != to.1 {
+			self.carve(ids, width, x, y);
+			y += if to.1 > y { 1 } else { -1 };
+		}
+		self.carve(ids, width, x, y);
+	}
+
+	/// Splits `area` and returns the centre of one room inside it.
+	fn split(&self, rng: &mut Rng, ids: &mut Vec<u32>, width: i32, area: Rectangle<i32>, depth: u32) -> (i32, i32) {
+		// A partition needs a wall along one side and a room at least one cell across.
+		let min_size = max(2, self.min_size);
+		let can_split_x = area.width >= min_size * 2;
+		let can_split_y = area.height >= min_size * 2;
+
+		if depth < self.max_depth && (can_split_x || can_split_y) {
+			let vertical = if can_split_x && can_split_y { rng.next_u64() % 2 == 0 } else { can_split_x };
+
+			let (first, second) = if vertical {
+				let cut = rng.range(min_size, area.width - min_size + 1);
+				(Rectangle::new(area.x, area.y, cut, area.height),
+				 Rectangle::new(area.x + cut, area.y, area.width - cut, area.height))
+			} else {
+				let cut = rng.range(min_size, area.height - min_size + 1);
+				(Rectangle::new(area.x, area.y, area.width, cut),
+				 Rectangle::new(area.x, area.y + cut, area.width, area.height - cut))
+			};
+
+			let a = self.split(rng, ids, width, first, depth + 1);
+			let b = self.split(rng, ids, width, second, depth + 1);
+			self.corridor(ids, width, a, b);
+			return if rng.next_u64() % 2 == 0 { a } else { b };
+		}
+
+		// Leave a wall between neighbouring partitions.
+		let room_width = max(1, rng.range(min(3, area.width - 2), area.width - 1));
+		let room_height = max(1, rng.range(min(3, area.height - 2), area.height - 1));
+		let room_x = area.x + 1 + rng.range(0, area.width - room_width - 1);
+		let room_y = area.y + 1 + rng.range(0, area.height - room_height - 1);
+
+		for y in room_y..(room_y + room_height) {
+			for x in room_x..(room_x + room_width) {
+				self.carve(ids, width, x, y);
+			}
+		}
+
+		(room_x + room_width / 2, room_y + room_height / 2)
+	}
+}
+
+impl MapGenerator for BspDungeon {
+	fn generate(&self, width: i32, height: i32, seed: u64) -> Vec<u32> {
+		let mut rng = Rng::new(seed);
+		let mut ids = vec![self.wall; (width * height) as usize];
+		if width < 3 || height < 3 {
+			return ids;
+		}
+
+		self.split(&mut rng, &mut ids, width, Rectangle::new(0, 0, width - 1, height - 1), 0);
+		ids
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use gen::{BspDungeon, CaveGenerator, MapGenerator, NoiseTerrain};
+
+	fn deterministic(generator: &MapGenerator) {
+		let first = generator.generate(48, 32, 7);
+		assert_eq!(first.len(), 48 * 32);
+		assert_eq!(generator.generate(48, 32, 7), first);
+		assert!(generator.generate(48, 32, 8) != first);
+	}
+
+	#[test]
+	fn noise_is_deterministic() {
+		deterministic(&NoiseTerrain::new());
+	}
+
+	#[test]
+	fn caves_are_deterministic() {
+		deterministic(&CaveGenerator::new());
+	}
+
+	#[test]
+	fn dungeons_are_deterministic() {
+		deterministic(&BspDungeon::new());
+	}
+
+	#[test]
+	fn dungeons_take_small_partitions() {
+		for &min_size in [-1, 0, 1, 2].iter() {
+			let mut dungeon = BspDungeon::new();
+			dungeon.min_size = min_size;
+			dungeon.max_depth = 8;
+
+			for &(width, height) in [(3, 3), (5, 17), (24, 16)].iter() {
+				let ids = dungeon.generate(width, height, 3);
+				assert_eq!(ids.len(), (width * height) as usize);
+				assert!(ids.contains(&dungeon.floor));
+				// The outer walls stay closed.
+				for x in 0..width {
+					assert_eq!(ids[(x + (height - 1) * width) as usize], dungeon.wall);
+					assert_eq!(ids[x as usize], dungeon.wall);
+				}
+			}
+		}
+	}
+}
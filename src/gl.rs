@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use image;
+use glium;
+use glium::Surface;
+use glium_text;
+
+use render::{Color, Matrix, Renderer, TextureId};
+use tile::TileAtlas;
+use vert::Vert;
+
+type Display = glium::backend::glutin_backend::GlutinFacade;
+
+const VERT_SHADER_SRC: &'static str = r#"
+	#version 140
+
+	in vec2 position;
+	in vec2 tex_coords;
+	out vec2 v_tex_coords;
+
+	uniform mat4 matrix;
+	void main() {
+		v_tex_coords = tex_coords;
+		gl_Position = matrix * vec4(position, 0.0, 1.0);
+	}
+"#;
+
+const FRAG_SHADER_SRC: &'static str = r#"
+	#version 140
+
+	in vec2 v_tex_coords;
+	out vec4 color;
+
+	uniform sampler2D tex;
+	uniform vec4 tint;
+	void main() {
+		color = texture(tex, v_tex_coords);
+		if (color.a == 0.0) { discard; }
+		color *= tint;
+	}
+"#;
+
+/// A quad covering -1 to 1 showing the texture from `bottom_left` to `top_right`.
+fn quad_verts(bottom_left: [f32; 2], top_right: [f32; 2]) -> Vec<Vert> {
+	let bottom_right = [top_right[0], bottom_left[1]];
+	let top_left = [bottom_left[0], top_right[1]];
+
+	let vert1 = Vert { position: [-1.0, -1.0], tex_coords: bottom_left };
+	let vert2 = Vert { position: [-1.0,  1.0], tex_coords: top_left };
+	let vert3 = Vert { position: [ 1.0, -1.0], tex_coords: bottom_right };
+	let vert4 = Vert { position: [ 1.0, -1.0], tex_coords: bottom_right };
+	let vert5 = Vert { position: [-1.0,  1.0], tex_coords: top_left };
+	let vert6 = Vert { position: [ 1.0,  1.0], tex_coords: top_right };
+	vec![vert1, vert2, vert3, vert4, vert5, vert6]
+}
+
+fn upload(display: &Display, img: image::RgbaImage) -> glium::texture::SrgbTexture2d {
+	let dims = img.dimensions();
+	let raw_img = glium::texture::RawImage2d::from_raw_rgba_reversed(img.into_raw(), dims);
+	glium::texture::SrgbTexture2d::new(display, raw_img).unwrap()
+}
+
+/// One quad per atlas entry, showing that entry of the sheet.
+fn sprite_verts(display: &Display, atlas: &TileAtlas) -> Vec<glium::VertexBuffer<Vert>> {
+	let mut tex_verts = Vec::with_capacity(atlas.num_entries as usize);
+	for entry in 0..atlas.num_entries {
+		let (bottom_left, top_right) = atlas.tex_coords(entry);
+		let buffer = glium::VertexBuffer::immutable(display, quad_verts(bottom_left, top_right).as_slice()).unwrap();
+		tex_verts.push(buffer);
+	}
+	tex_verts
+}
+
+/// Draws with OpenGL into the window, one frame between `begin` and `finish`.
+pub struct GliumRenderer<'d> {
+	display: &'d Display,
+	program: glium::Program,
+	text_system: glium_text::TextSystem,
+	font: glium_text::FontTexture,
+	/// The whole texture on a unit quad, for `draw_texture` and `draw_quad`.
+	quad: glium::VertexBuffer<Vert>,
+	/// One opaque white texel, tinted by `draw_quad`.
+	white: glium::texture::SrgbTexture2d,
+	/// Quads for each atlas' entries, built the first time it is drawn from,
+	/// by the id of its sheet.
+	sprites: HashMap<TextureId, Vec<glium::VertexBuffer<Vert>>>,
+	textures: Vec<glium::texture::SrgbTexture2d>,
+	frame: Option<glium::Frame>,
+}
+
+impl<'d> GliumRenderer<'d> {
+	pub fn new<P: AsRef<Path>>(display: &'d Display, font_path: P) -> GliumRenderer<'d> {
+		let program = glium::Program::from_source(display, VERT_SHADER_SRC, FRAG_SHADER_SRC, None).unwrap();
+		let text_system = glium_text::TextSystem::new(display);
+		let font_file = File::open(font_path).unwrap();
+		let font = glium_text::FontTexture::new(display, font_file, 24).unwrap();
+
+		GliumRenderer {
+			display: display,
+			program: program,
+			text_system: text_system,
+			font: font,
+			quad: glium::VertexBuffer::immutable(display, quad_verts([0.0, 0.0], [1.0, 1.0]).as_slice()).unwrap(),
+			white: glium::texture::SrgbTexture2d::new(display, vec![vec![(255u8, 255u8, 255u8, 255u8)]]).unwrap(),
+			sprites: HashMap::new(),
+			textures: Vec::new(),
+			frame: None,
+		}
+	}
+
+	/// Starts drawing a frame. Draw calls outside `begin` and `finish` panic.
+	pub fn begin(&mut self) {
+		self.frame = Some(self.display.draw());
+	}
+
+	/// Shows the frame drawn since `begin`.
+	pub fn finish(&mut self) {
+		if let Some(frame) = self.frame.take() {
+			frame.finish().unwrap();
+		}
+	}
+}
+
+impl<'d> Renderer for GliumRenderer<'d> {
+	fn load_texture(&mut self, img: image::RgbaImage) -> TextureId {
+		self.textures.push(upload(self.display, img));
+		TextureId(self.textures.len() - 1)
+	}
+
+	fn clear(&mut self, color: Color) {
+		let target = self.frame.as_mut().expect("drawing outside of a frame");
+		target.clear_color(color.0, color.1, color.2, color.3);
+	}
+
+	fn draw_sprite(&mut self, atlas: &TileAtlas, entry: u32, matrix: Matrix, opacity: f32) {
+		let texture = match atlas.texture {
+			Some(texture) => texture,
+			None => { return; },
+		};
+		if !self.sprites.contains_key(&texture) {
+			let verts = sprite_verts(self.display, atlas);
+			self.sprites.insert(texture, verts);
+		}
+
+		let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+		let buffer = match self.sprites[&texture].get(entry as usize) {
+			Some(buffer) => buffer,
+			None => { return; },
+		};
+
+		let uniform = uniform! {
+			matrix: matrix,
+			tint: [1.0, 1.0, 1.0, opacity],
+			tex: self.textures[texture.0].sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+		};
+
+		let params = glium::DrawParameters {
+			blend: glium::Blend::alpha_blending(),
+			.. Default::default()
+		};
+
+		let target = self.frame.as_mut().expect("drawing outside of a frame");
+		target.draw(buffer, &indices, &self.program, &uniform, &params).unwrap();
+	}
+
+	fn draw_texture(&mut self, texture: TextureId, matrix: Matrix, opacity: f32) {
+		let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+		let uniform = uniform! {
+			matrix: matrix,
+			tint: [1.0, 1.0, 1.0, opacity],
+			tex: self.textures[texture.0].sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+		};
+
+		let params = glium::DrawParameters {
+			blend: glium::Blend::alpha_blending(),
+			.. Default::default()
+		};
+
+		let target = self.frame.as_mut().expect("drawing outside of a frame");
+		target.draw(&self.quad, &indices, &self.program, &uniform, &params).unwrap();
+	}
+
+	fn draw_quad(&mut self, matrix: Matrix, color: Color) {
+		let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+		let uniform = uniform! {
+			matrix: matrix,
+			tint: [color.0, color.1, color.2, color.3],
+			tex: &self.white,
+		};
+
+		let params = glium::DrawParameters {
+			blend: glium::Blend::alpha_blending(),
+			.. Default::default()
+		};
+
+		let target = self.frame.as_mut().expect("drawing outside of a frame");
+		target.draw(&self.quad, &indices, &self.program, &uniform, &params).unwrap();
+	}
+
+	fn draw_text(&mut self, text: &str, matrix: Matrix, color: Color) {
+		let text = glium_text::TextDisplay::new(&self.text_system, &self.font, text);
+		let target = self.frame.as_mut().expect("drawing outside of a frame");
+		glium_text::draw(&text, &self.text_system, target, matrix, color);
+	}
+}
@@ -0,0 +1,86 @@
+//! Running the game without a window, for scripted gameplay checks.
+//!
+//! ```
+//! use tilepaste::game::Game;
+//! use tilepaste::gen::GridGenerator;
+//! use tilepaste::harness::Harness;
+//! use tilepaste::keyboard::Action;
+//! use tilepaste::tile::TileAtlas;
+//!
+//! let atlas = TileAtlas::new(16, 16);
+//! let mut harness = Harness::new(Game::new(16.0 / 9.0, &atlas, &GridGenerator::new(), 0));
+//! let start = harness.game.player.pos.x;
+//! harness.hold(Action::Right, 30);
+//! assert!(harness.game.player.pos.x > start);
+//! ```
+
+use Scene;
+use SceneTrans;
+use TICK_RATE;
+use game::Game;
+use keyboard::{Action, Inputs, KeyState};
+
+/// Steps a `Game` one fixed tick at a time with synthetic input.
+pub struct Harness<'a> {
+	pub game: Game<'a>,
+	pub inputs: Inputs,
+	/// Length of one tick in seconds.
+	pub dt: f32,
+	/// Ticks run so far.
+	pub ticks: u64,
+	/// What the game asked for on the last tick.
+	pub state: SceneTrans,
+}
+
+impl<'a> Harness<'a> {
+	pub fn new(game: Game<'a>) -> Harness<'a> {
+		Harness {
+			game: game,
+			inputs: Inputs::new(),
+			dt: 1.0 / TICK_RATE,
+			ticks: 0,
+			state: SceneTrans::Game,
+		}
+	}
+
+	pub fn press(&mut self, action: Action) {
+		self.inputs.keys.insert(action, KeyState::Pressed);
+	}
+
+	pub fn release(&mut self, action: Action) {
+		self.inputs.keys.insert(action, KeyState::Released);
+	}
+
+	/// Runs one tick with the actions currently pressed.
+	pub fn step(&mut self) -> &SceneTrans {
+		self.state = self.game.handle_input(&self.inputs, None, None, self.dt);
+		self.ticks += 1;
+		&self.state
+	}
+
+	/// Runs `ticks` ticks with the actions currently pressed.
+	pub fn run(&mut self, ticks: u32) {
+		for _ in 0..ticks {
+			self.step();
+		}
+	}
+
+	/// Presses `action`, runs `ticks` ticks and lets go.
+	pub fn hold(&mut self, action: Action, ticks: u32) {
+		self.press(action);
+		self.run(ticks);
+		self.release(action);
+	}
+
+	/// Runs ticks until `done` is true or `limit` ticks have passed, returning
+	/// whether it came true.
+	pub fn run_until<F: Fn(&Game<'a>) -> bool>(&mut self, limit: u32, done: F) -> bool {
+		for _ in 0..limit {
+			if done(&self.game) {
+				return true;
+			}
+			self.step();
+		}
+		done(&self.game)
+	}
+}
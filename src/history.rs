@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+
+use entity::Entity;
+use map::{Map, MapError};
+use save::EntityData;
+use tile::EMPTY_TILE;
+
+/// A reversible change to a map.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Edit {
+	Tile { layer: usize, x: i32, y: i32, before: u32, after: u32 },
+	/// Several cells of one layer set to the same id, with what each held before.
+	Region { layer: usize, cells: Vec<(i32, i32, u32)>, after: u32 },
+	AddEntity { index: usize, entity: EntityData },
+	RemoveEntity { index: usize, entity: EntityData },
+	/// Cells the map's autotiler re-picked after the edit before it, with
+	/// what each held before that edit and after the autotiler was done.
+	Restyle { layer: usize, cells: Vec<(i32, i32, u32, u32)> },
+}
+
+fn insert_entity(map: &mut Map, index: usize, entity: &EntityData) -> Result<(), MapError> {
+	if index > map.entities().len() {
+		return Err(MapError::NoEntity(index));
+	}
+	let atlas = map.atlas;
+	map.insert_entity(index, Entity::new(entity.x, entity.y, entity.tex_id, atlas));
+	Ok(())
+}
+
+fn remove_entity(map: &mut Map, index: usize) -> Result<(), MapError> {
+	if index >= map.entities().len() {
+		return Err(MapError::NoEntity(index));
+	}
+	map.remove_entity(index);
+	Ok(())
+}
+
+impl Edit {
+	/// Replays the edit. Tiles are written as recorded, without autotiling;
+	/// what the autotiler did the first time follows as a `Restyle`.
+	pub fn apply(&self, map: &mut Map) -> Result<(), MapError> {
+		match *self {
+			Edit::Tile { layer, x, y, after, .. } => map.set_layer_raw(layer, x, y, after),
+			Edit::Region { layer, ref cells, after } => {
+				for &(x, y, _) in cells.iter() {
+					try!(map.set_layer_raw(layer, x, y, after));
+				}
+				Ok(())
+			},
+			Edit::AddEntity { index, ref entity } => insert_entity(map, index, entity),
+			Edit::RemoveEntity { index, .. } => remove_entity(map, index),
+			Edit::Restyle { layer, ref cells } => {
+				for &(x, y, _, after) in cells.iter() {
+					try!(map.set_layer_raw(layer, x, y, after));
+				}
+				Ok(())
+			},
+		}
+	}
+
+	pub fn revert(&self, map: &mut Map) -> Result<(), MapError> {
+		match *self {
+			Edit::Tile { layer, x, y, before, .. } => map.set_layer_raw(layer, x, y, before),
+			Edit::Region { layer, ref cells, .. } => {
+				for &(x, y, before) in cells.iter().rev() {
+					try!(map.set_layer_raw(layer, x, y, before));
+				}
+				Ok(())
+			},
+			Edit::AddEntity { index, .. } => remove_entity(map, index),
+			Edit::RemoveEntity { index, ref entity } => insert_entity(map, index, entity),
+			Edit::Restyle { layer, ref cells } => {
+				for &(x, y, before, _) in cells.iter().rev() {
+					try!(map.set_layer_raw(layer, x, y, before));
+				}
+				Ok(())
+			},
+		}
+	}
+
+	/// The layer, cells and id of a tile edit.
+	fn tiles(&self) -> Option<(usize, Vec<(i32, i32)>, u32)> {
+		match *self {
+			Edit::Tile { layer, x, y, after, .. } => Some((layer, vec![(x, y)], after)),
+			Edit::Region { layer, ref cells, after } => Some((layer, cells.iter().map(|&(x, y, _)| (x, y)).collect(), after)),
+			_ => None,
+		}
+	}
+}
+
+/// Every cell the autotiler may touch when `cells` change: the cells
+/// themselves and their eight neighbours, as the map resolves them.
+fn surroundings(map: &Map, cells: &[(i32, i32)]) -> Vec<(i32, i32)> {
+	let mut around = Vec::with_capacity(cells.len() * 9);
+	for &(x, y) in cells {
+		for dy in -1..2 {
+			for dx in -1..2 {
+				if let Ok(cell) = map.resolve_write(x + dx, y + dy) {
+					around.push(cell);
+				}
+			}
+		}
+	}
+	around.sort();
+	around.dedup();
+	around
+}
+
+/// Bounded undo/redo history of map edits. Edits made between `begin_group`
+/// and `end_group`, such as one drag stroke, undo as a single step.
+pub struct History {
+	pub limit: usize,
+	undo: VecDeque<Vec<Edit>>,
+	redo: Vec<Vec<Edit>>,
+	group: Option<Vec<Edit>>,
+}
+
+impl History {
+	pub fn new(limit: usize) -> History {
+		History {
+			limit: limit,
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			group: None,
+		}
+	}
+
+	pub fn begin_group(&mut self) {
+		self.end_group();
+		self.group = Some(Vec::new());
+	}
+
+	pub fn end_group(&mut self) {
+		if let Some(group) = self.group.take() {
+			self.push(group);
+		}
+	}
+
+	fn push(&mut self, step: Vec<Edit>) {
+		if step.is_empty() {
+			return;
+		}
+
+		self.redo.clear();
+		self.undo.push_back(step);
+		while self.undo.len() > self.limit {
+			self.undo.pop_front();
+		}
+	}
+
+	/// Applies `edit` to the map and records it. Tile edits go through the
+	/// map's autotiler, and the neighbours it re-picks are recorded with them.
+	pub fn apply(&mut self, map: &mut Map, edit: Edit) -> Result<(), MapError> {
+		let mut step = Vec::new();
+		match edit.tiles() {
+			Some((layer, mut cells, after)) => {
+				let around = surroundings(map, &cells);
+				let before: Vec<u32> = around.iter().map(|&(x, y)| map.get_layer(layer, x, y).map_or(EMPTY_TILE, |tile| tile.tex_id)).collect();
+				for &(x, y) in cells.iter() {
+					try!(map.set_layer(layer, x, y, after));
+				}
+
+				cells.sort();
+				let mut restyled = Vec::new();
+				for (&(x, y), &was) in around.iter().zip(before.iter()) {
+					let now = map.get_layer(layer, x, y).map_or(EMPTY_TILE, |tile| tile.tex_id);
+					let written = if cells.binary_search(&(x, y)).is_ok() { after } else { was };
+					if now != written {
+						restyled.push((x, y, was, now));
+					}
+				}
+
+				step.push(edit);
+				if !restyled.is_empty() {
+					step.push(Edit::Restyle { layer: layer, cells: restyled });
+				}
+			},
+			None => {
+				try!(edit.apply(map));
+				step.push(edit);
+			},
+		}
+
+		match self.group {
+			Some(ref mut group) => group.extend(step),
+			None => self.push(step),
+		}
+		Ok(())
+	}
+
+	/// Sets one cell, recorded at the coordinates the map's edge policy resolves
+	/// it to. Cells the map refuses are skipped.
+	pub fn set_tile(&mut self, map: &mut Map, layer: usize, x: i32, y: i32, id: u32) -> Result<(), MapError> {
+		let (x, y) = match map.resolve_write(x, y) {
+			Ok(cell) => cell,
+			Err(_) => { return Ok(()); },
+		};
+		let before = match map.get_layer(layer, x, y) {
+			Some(tile) => tile.tex_id,
+			None => { return Ok(()); },
+		};
+		if before == id {
+			return Ok(());
+		}
+		self.apply(map, Edit::Tile { layer: layer, x: x, y: y, before: before, after: id })
+	}
+
+	/// Sets every cell in `cells` the map accepts a write to `id` as one edit.
+	pub fn fill(&mut self, map: &mut Map, layer: usize, cells: &[(i32, i32)], id: u32) -> Result<(), MapError> {
+		let mut changed = Vec::new();
+		for &(x, y) in cells {
+			let (x, y) = match map.resolve_write(x, y) {
+				Ok(cell) => cell,
+				Err(_) => { continue; },
+			};
+			if changed.iter().any(|&(cx, cy, _)| cx == x && cy == y) {
+				continue;
+			}
+			if let Some(tile) = map.get_layer(layer, x, y) {
+				if tile.tex_id != id {
+					changed.push((x, y, tile.tex_id));
+				}
+			}
+		}
+		if changed.is_empty() {
+			return Ok(());
+		}
+		self.apply(map, Edit::Region { layer: layer, cells: changed, after: id })
+	}
+
+	pub fn add_entity(&mut self, map: &mut Map, x: f32, y: f32, tex_id: u32) -> Result<(), MapError> {
+		let index = map.entities().len();
+		self.apply(map, Edit::AddEntity { index: index, entity: EntityData { x: x, y: y, tex_id: tex_id } })
+	}
+
+	pub fn remove_entity(&mut self, map: &mut Map, index: usize) -> Result<(), MapError> {
+		let entity = match map.entity(index) {
+			Some(entity) => EntityData { x: entity.x, y: entity.y, tex_id: entity.tex_id },
+			None => { return Err(MapError::NoEntity(index)); },
+		};
+		self.apply(map, Edit::RemoveEntity { index: index, entity: entity })
+	}
+
+	pub fn can_undo(&self) -> bool {
+		!self.undo.is_empty()
+	}
+
+	pub fn can_redo(&self) -> bool {
+		!self.redo.is_empty()
+	}
+
+	/// Reverts the last step, returning false if there was none. If the map
+	/// refuses part of it, the map no longer matches the history, so the
+	/// history is cleared and the error returned.
+	pub fn undo(&mut self, map: &mut Map) -> Result<bool, MapError> {
+		self.end_group();
+		let step = match self.undo.pop_back() {
+			Some(step) => step,
+			None => { return Ok(false); },
+		};
+		for edit in step.iter().rev() {
+			if let Err(e) = edit.revert(map) {
+				self.clear();
+				return Err(e);
+			}
+		}
+		self.redo.push(step);
+		Ok(true)
+	}
+
+	/// Applies the last undone step again, failing the same way as `undo`.
+	pub fn redo(&mut self, map: &mut Map) -> Result<bool, MapError> {
+		self.end_group();
+		let step = match self.redo.pop() {
+			Some(step) => step,
+			None => { return Ok(false); },
+		};
+		for edit in step.iter() {
+			if let Err(e) = edit.apply(map) {
+				self.clear();
+				return Err(e);
+			}
+		}
+		self.undo.push_back(step);
+		Ok(true)
+	}
+
+	pub fn clear(&mut self) {
+		self.undo.clear();
+		self.redo.clear();
+		self.group = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use autotile::{AutoTileRule, AutoTiler, MaskKind};
+	use history::History;
+	use map::{Map, MapError};
+	use tile::{TileAtlas, EMPTY_TILE};
+
+	fn blank(atlas: &TileAtlas) -> Map {
+		Map::from_ids(6, 6, vec![EMPTY_TILE; 36], Vec::new(), 10.0, 1.0, atlas)
+	}
+
+	fn id(map: &Map, x: i32, y: i32) -> u32 {
+		map.get(x, y).unwrap().tex_id
+	}
+
+	#[test]
+	fn undoes_and_redoes_paint() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+
+		history.fill(&mut map, 0, &[(1, 1), (2, 1), (9, 9)], 3).unwrap();
+		history.set_tile(&mut map, 0, 2, 1, 5).unwrap();
+		assert_eq!((id(&map, 1, 1), id(&map, 2, 1)), (3, 5));
+
+		assert_eq!(history.undo(&mut map), Ok(true));
+		assert_eq!((id(&map, 1, 1), id(&map, 2, 1)), (3, 3));
+		assert_eq!(history.undo(&mut map), Ok(true));
+		assert_eq!((id(&map, 1, 1), id(&map, 2, 1)), (EMPTY_TILE, EMPTY_TILE));
+		assert_eq!(history.undo(&mut map), Ok(false));
+
+		assert_eq!(history.redo(&mut map), Ok(true));
+		assert_eq!(history.redo(&mut map), Ok(true));
+		assert_eq!((id(&map, 1, 1), id(&map, 2, 1)), (3, 5));
+		assert_eq!(history.redo(&mut map), Ok(false));
+	}
+
+	#[test]
+	fn undoes_and_redoes_entities() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+
+		history.add_entity(&mut map, 1.0, 1.0, 4).unwrap();
+		history.add_entity(&mut map, 2.0, 2.0, 6).unwrap();
+		history.remove_entity(&mut map, 0).unwrap();
+		assert_eq!(map.entities().iter().map(|entity| entity.tex_id).collect::<Vec<u32>>(), vec![6]);
+		assert_eq!(history.remove_entity(&mut map, 3), Err(MapError::NoEntity(3)));
+
+		history.undo(&mut map).unwrap();
+		assert_eq!(map.entities().iter().map(|entity| entity.tex_id).collect::<Vec<u32>>(), vec![4, 6]);
+		assert_eq!(map.entities_near(1.0, 1.0, 0.1), vec![0]);
+		history.undo(&mut map).unwrap();
+		assert_eq!(map.entities().len(), 1);
+
+		history.redo(&mut map).unwrap();
+		history.redo(&mut map).unwrap();
+		assert_eq!(map.entities().iter().map(|entity| entity.tex_id).collect::<Vec<u32>>(), vec![6]);
+	}
+
+	#[test]
+	fn forgets_the_oldest_steps_past_the_limit() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(2);
+
+		for x in 0..3 {
+			history.set_tile(&mut map, 0, x, 0, 1).unwrap();
+		}
+		assert_eq!(history.undo(&mut map), Ok(true));
+		assert_eq!(history.undo(&mut map), Ok(true));
+		assert_eq!(history.undo(&mut map), Ok(false));
+		assert_eq!((id(&map, 0, 0), id(&map, 1, 0), id(&map, 2, 0)), (1, EMPTY_TILE, EMPTY_TILE));
+	}
+
+	#[test]
+	fn a_new_edit_discards_redo() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+
+		history.set_tile(&mut map, 0, 0, 0, 1).unwrap();
+		history.undo(&mut map).unwrap();
+		assert!(history.can_redo());
+
+		history.set_tile(&mut map, 0, 1, 0, 2).unwrap();
+		assert!(!history.can_redo());
+		assert_eq!(history.redo(&mut map), Ok(false));
+		assert_eq!(id(&map, 0, 0), EMPTY_TILE);
+	}
+
+	#[test]
+	fn restores_the_neighbours_the_autotiler_changed() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut rule = AutoTileRule::new(MaskKind::Cardinal, (0..16).collect());
+		rule.connect_edges = false;
+		map.set_autotiler(AutoTiler::new(vec![rule]));
+		let mut history = History::new(8);
+
+		// A variant placed by hand, which the autotiler wouldn't pick here.
+		map.set_layer_raw(0, 2, 2, 7).unwrap();
+		history.set_tile(&mut map, 0, 3, 2, 0).unwrap();
+		let (painted, neighbour) = (id(&map, 3, 2), id(&map, 2, 2));
+		assert!(neighbour != 7);
+
+		history.undo(&mut map).unwrap();
+		assert_eq!((id(&map, 3, 2), id(&map, 2, 2)), (EMPTY_TILE, 7));
+
+		history.redo(&mut map).unwrap();
+		assert_eq!((id(&map, 3, 2), id(&map, 2, 2)), (painted, neighbour));
+	}
+
+	#[test]
+	fn a_refused_replay_clears_the_history() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+
+		history.set_tile(&mut map, 0, 0, 0, 1).unwrap();
+		history.add_entity(&mut map, 1.0, 1.0, 4).unwrap();
+		map.remove_entity(0);
+
+		assert_eq!(history.undo(&mut map), Err(MapError::NoEntity(0)));
+		assert!(!history.can_undo() && !history.can_redo());
+	}
+}
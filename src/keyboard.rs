@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
+#[cfg(feature = "gl")]
 use glium;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum Action {
     Up,
     Down,
@@ -12,6 +13,43 @@ pub enum Action {
     Enter,
     Quit,
     Back,
+    Tool,
+    Layer,
+    Save,
+    Undo,
+    Redo,
+    Next,
+    Rotate,
+    Mirror,
+}
+
+/// Every action, in the order `Inputs::key_bits` packs them.
+pub const ACTIONS: [Action; 16] = [
+    Action::Up, Action::Down, Action::Left, Action::Right,
+    Action::Space, Action::Enter, Action::Quit, Action::Back,
+    Action::Tool, Action::Layer, Action::Save, Action::Undo,
+    Action::Redo, Action::Next, Action::Rotate, Action::Mirror,
+];
+
+/// Mouse buttons as scenes see them, so they don't depend on the windowing library.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u8),
+}
+
+#[cfg(feature = "gl")]
+impl From<glium::glutin::MouseButton> for MouseButton {
+    fn from(button: glium::glutin::MouseButton) -> MouseButton {
+        match button {
+            glium::glutin::MouseButton::Left => MouseButton::Left,
+            glium::glutin::MouseButton::Right => MouseButton::Right,
+            glium::glutin::MouseButton::Middle => MouseButton::Middle,
+            glium::glutin::MouseButton::Other(n) => MouseButton::Other(n),
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -22,6 +60,8 @@ pub enum KeyState {
 
 pub struct Inputs {
     pub keys: HashMap<Action, KeyState>,
+    pub mouse_left: KeyState,
+    pub mouse_right: KeyState,
 }
 
 impl Inputs {
@@ -35,12 +75,23 @@ impl Inputs {
         keys.insert(Action::Space, KeyState::Released);
         keys.insert(Action::Back, KeyState::Released);
         keys.insert(Action::Quit, KeyState::Released);
+        keys.insert(Action::Tool, KeyState::Released);
+        keys.insert(Action::Layer, KeyState::Released);
+        keys.insert(Action::Save, KeyState::Released);
+        keys.insert(Action::Undo, KeyState::Released);
+        keys.insert(Action::Redo, KeyState::Released);
+        keys.insert(Action::Next, KeyState::Released);
+        keys.insert(Action::Rotate, KeyState::Released);
+        keys.insert(Action::Mirror, KeyState::Released);
 
         Inputs {
             keys: keys,
+            mouse_left: KeyState::Released,
+            mouse_right: KeyState::Released,
         }
     }
 
+    #[cfg(feature = "gl")]
     pub fn get(&mut self, key: glium::glutin::VirtualKeyCode) -> Option<(Action, &mut KeyState)> {
         match key {
             glium::glutin::VirtualKeyCode::W => Some((Action::Up, self.keys.get_mut(&Action::Up).unwrap())),
@@ -51,10 +102,19 @@ impl Inputs {
             glium::glutin::VirtualKeyCode::Q => Some((Action::Quit, self.keys.get_mut(&Action::Quit).unwrap())),
             glium::glutin::VirtualKeyCode::Escape => Some((Action::Back, self.keys.get_mut(&Action::Back).unwrap())),
             glium::glutin::VirtualKeyCode::Return => Some((Action::Enter, self.keys.get_mut(&Action::Enter).unwrap())),
+            glium::glutin::VirtualKeyCode::T => Some((Action::Tool, self.keys.get_mut(&Action::Tool).unwrap())),
+            glium::glutin::VirtualKeyCode::L => Some((Action::Layer, self.keys.get_mut(&Action::Layer).unwrap())),
+            glium::glutin::VirtualKeyCode::F5 => Some((Action::Save, self.keys.get_mut(&Action::Save).unwrap())),
+            glium::glutin::VirtualKeyCode::Z => Some((Action::Undo, self.keys.get_mut(&Action::Undo).unwrap())),
+            glium::glutin::VirtualKeyCode::Y => Some((Action::Redo, self.keys.get_mut(&Action::Redo).unwrap())),
+            glium::glutin::VirtualKeyCode::Tab => Some((Action::Next, self.keys.get_mut(&Action::Next).unwrap())),
+            glium::glutin::VirtualKeyCode::R => Some((Action::Rotate, self.keys.get_mut(&Action::Rotate).unwrap())),
+            glium::glutin::VirtualKeyCode::M => Some((Action::Mirror, self.keys.get_mut(&Action::Mirror).unwrap())),
             _ => None,
         }
     }
 
+    #[cfg(feature = "gl")]
     pub fn update(&mut self, key: glium::glutin::VirtualKeyCode, new_state: glium::glutin::ElementState) {
         let key = self.get(key);
         if key.is_some() {
@@ -72,6 +132,39 @@ impl Inputs {
         }
     }
 
+    #[cfg(feature = "gl")]
+    pub fn update_mouse(&mut self, button: MouseButton, new_state: glium::glutin::ElementState) {
+        let state = if new_state == glium::glutin::ElementState::Pressed { KeyState::Pressed } else { KeyState::Released };
+        match button {
+            MouseButton::Left => { self.mouse_left = state; },
+            MouseButton::Right => { self.mouse_right = state; },
+            _ => { },
+        }
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.keys.get(&action) == Some(&KeyState::Pressed)
+    }
+
+    /// Pressed actions as one bit each, in `ACTIONS` order.
+    pub fn key_bits(&self) -> u32 {
+        let mut bits = 0;
+        for (i, action) in ACTIONS.iter().enumerate() {
+            if self.is_pressed(*action) {
+                bits |= 1 << i;
+            }
+        }
+        bits
+    }
+
+    /// Sets every action from bits packed by `key_bits`.
+    pub fn set_key_bits(&mut self, bits: u32) {
+        for (i, action) in ACTIONS.iter().enumerate() {
+            let state = if bits & (1 << i) != 0 { KeyState::Pressed } else { KeyState::Released };
+            self.keys.insert(*action, state);
+        }
+    }
+
     pub fn has_update(&self) -> bool {
         for key in self.keys.iter() {
             if *key.1 == KeyState::Pressed {
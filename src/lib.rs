@@ -0,0 +1,67 @@
+#[cfg(feature = "gl")]
+#[macro_use]
+extern crate glium;
+extern crate image;
+#[cfg(feature = "gl")]
+extern crate glium_text;
+extern crate xml;
+extern crate rustc_serialize;
+extern crate rusttype;
+
+pub mod utils;
+pub mod map;
+pub mod chunk;
+pub mod gen;
+pub mod autotile;
+pub mod fov;
+pub mod fog;
+pub mod path;
+pub mod ray;
+pub mod collide;
+pub mod spatial;
+pub mod tile;
+pub mod render;
+#[cfg(feature = "gl")]
+pub mod gl;
+pub mod soft;
+pub mod props;
+pub mod anim;
+pub mod entity;
+pub mod player;
+pub mod controller;
+#[cfg(feature = "gl")]
+pub mod vert;
+pub mod keyboard;
+pub mod game;
+pub mod timestep;
+pub mod replay;
+pub mod harness;
+pub mod menu;
+pub mod editor;
+pub mod history;
+pub mod stamp;
+pub mod tiled;
+pub mod save;
+
+use keyboard::{Inputs, MouseButton};
+use render::Renderer;
+
+/// Simulation ticks per second.
+pub const TICK_RATE: f32 = 60.0;
+/// Most ticks run to catch up after a slow frame.
+pub const MAX_STEPS: u32 = 5;
+
+#[derive(PartialEq)]
+pub enum SceneTrans {
+	Quit,
+	Menu,
+	Game,
+	Editor,
+}
+
+/// `handle_input` runs once per fixed tick of `dt` seconds; `draw` runs once
+/// per frame, `alpha` of the way from the last tick to the next.
+pub trait Scene {
+    fn handle_input(&mut self, inputs: &Inputs, mouse_coords: Option<(i32, i32)>, clicked: Option<MouseButton>, dt: f32) -> SceneTrans;
+	fn draw(&mut self, renderer: &mut Renderer, alpha: f32);
+}
@@ -1,39 +1,46 @@
-#[macro_use]
 extern crate glium;
-extern crate image;
-extern crate glium_text;
 extern crate time;
-
-pub mod utils;
-pub mod map;
-pub mod tile;
-pub mod entity;
-pub mod player;
-pub mod vert;
-pub mod keyboard;
-pub mod game;
-pub mod menu;
-
-use glium::{DisplayBuild, Surface};
-
-use keyboard::Inputs;
-use menu::Menu;
-use game::Game;
-use tile::TileAtlas;
-
-#[derive(PartialEq)]
-pub enum SceneTrans {
-	Quit,
-	Menu,
-	Game,
-}
-
-pub trait Scene<'a> {
-    fn handle_input(&mut self, inputs: &Inputs, mouse_coords: Option<(i32, i32)>, clicked: Option<glium::glutin::MouseButton>, dt: f32) -> SceneTrans;
-	fn draw(&mut self, mut target: &mut glium::Frame, program: &glium::Program, text_system: &'a glium_text::TextSystem, font: &'a glium_text::FontTexture);
+extern crate tilepaste;
+
+use std::collections::VecDeque;
+
+use glium::DisplayBuild;
+
+use tilepaste::{Scene, SceneTrans, MAX_STEPS, TICK_RATE};
+use tilepaste::keyboard::{Inputs, MouseButton};
+use tilepaste::menu::Menu;
+use tilepaste::game::Game;
+use tilepaste::editor::Editor;
+use tilepaste::gen::GridGenerator;
+use tilepaste::tile::TileAtlas;
+use tilepaste::render::Renderer;
+use tilepaste::gl::GliumRenderer;
+use tilepaste::timestep::FixedStep;
+use tilepaste::replay::{Replay, Tick};
+
+/// The argument following `flag` on the command line.
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+	args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1)).cloned()
 }
 
 fn main() {
+	// --record <file> saves every tick's input; --replay <file> plays one back
+	// instead of reading the keyboard and mouse.
+	let args: Vec<String> = std::env::args().collect();
+	let mut recording = arg_value(&args, "--record").map(|path| (path, Replay::new(TICK_RATE)));
+	let playback = match arg_value(&args, "--replay") {
+		Some(path) => match Replay::load(&path) {
+			Ok(replay) => Some(replay),
+			Err(e) => {
+				println!("could not load replay {}: {}", path, e);
+				return;
+			},
+		},
+		None => None,
+	};
+	let mut played = 0;
+	let mut desynced = false;
+
 	let mut width = 1280;
 	let mut height = 720;
 
@@ -44,53 +51,33 @@ fn main() {
 		.build_glium().unwrap();
 
 	let mut ratio = width as f32 / height as f32;
-	let atlas = TileAtlas::new(&display, 16, 16);
-
-	let vert_shader_src = r#"
-		#version 140
-
-		in vec2 position;
-		in vec2 tex_coords;
-		out vec2 v_tex_coords;
-
-		uniform mat4 matrix;
-		void main() {
-			v_tex_coords = tex_coords;
-			gl_Position = matrix * vec4(position, 0.0, 1.0);
-		}
-	"#;
-
-	let frag_shader_src = r#"
-		#version 140
-
-		in vec2 v_tex_coords;
-		out vec4 color;
-
-		uniform sampler2D tex;
-		void main() {
-			color = texture(tex, v_tex_coords);
-			if (color.a == 0.0) { discard; }
-		}
-	"#;
-
-	let program = glium::Program::from_source(&display, vert_shader_src, frag_shader_src, None).unwrap();
-	let text_system = glium_text::TextSystem::new(&display);
-	let font_file = std::fs::File::open(&std::path::Path::new("assets/ubuntu.ttf")).unwrap();
-	let font = glium_text::FontTexture::new(&display, font_file, 24).unwrap();
-	let mut menu = Menu::new(&display, "assets/main_menu.png", ratio);
-	let mut game = Game::new(ratio, &atlas);
-
-	let mut dt = 0.0;
-    let mut accum_dt = 0.0;
+	let mut atlas = TileAtlas::new(16, 16);
+
+	let mut renderer = GliumRenderer::new(&display, "assets/ubuntu.ttf");
+	renderer.load_atlas(&mut atlas);
+	let mut menu = Menu::new(&mut renderer, "assets/main_menu.png", ratio);
+	let mut game = Game::new(ratio, &atlas, &GridGenerator::new(), 0);
+	// Replays only check the game, so the editor mustn't depend on files a
+	// recorded run may have saved over.
+	let mut editor = if recording.is_some() || playback.is_some() {
+		Editor::scratch(&atlas, ratio, width as f32, height as f32)
+	} else {
+		Editor::new(&atlas, ratio, width as f32, height as f32, "assets/level.tpm", "assets/stamps.tps")
+	};
+
+	let mut step = FixedStep::new(playback.as_ref().map_or(TICK_RATE, |replay| replay.tick_rate), MAX_STEPS);
+	let mut last_time = time::precise_time_ns();
+    let mut accum_time = 0.0;
     let mut frames = 0;
 	let mut coords = None;
 	let mut game_state = SceneTrans::Menu;
     let mut inputs = Inputs::new();
+	// Mouse buttons are handed to ticks one event at a time, so a press and
+	// release in the same frame reach the scenes on separate ticks, and a
+	// frame that runs no ticks loses nothing.
+	let mut mouse = VecDeque::new();
 
 	loop {
-		let start_time = time::precise_time_ns();
-
-		let mut mouse = None;
 		for event in display.poll_events() {
 			match event {
 				glium::glutin::Event::Closed => { game_state = SceneTrans::Quit; },
@@ -101,46 +88,100 @@ fn main() {
                     }
 				},
 				glium::glutin::Event::MouseMoved(c) => { coords = Some(c); },
-				glium::glutin::Event::MouseInput(_, b) => { mouse = Some(b); },
-				glium::glutin::Event::Resized(tmp_height, tmp_width) => {
-					height = tmp_height;
-					width = tmp_width;
-					ratio = (height as f32) / (width as f32);
+				glium::glutin::Event::MouseInput(state, b) => {
+					if playback.is_none() {
+						mouse.push_back((MouseButton::from(b), state));
+					}
+				},
+				glium::glutin::Event::Resized(new_width, new_height) => {
+					width = new_width;
+					height = new_height;
+					ratio = (width as f32) / (height as f32);
+					editor.window = (width as f32, height as f32);
 				},
 				_ => (),
 			}
 		}
 
+		let now = time::precise_time_ns();
+		let elapsed = (now - last_time) as f32 / 1e9;
+		last_time = now;
+		atlas.advance(elapsed * 1000.0);
+
+		for _ in 0..step.advance(elapsed) {
+			if game_state == SceneTrans::Quit {
+				break;
+			}
+
+			let mut tick = match playback {
+				Some(ref replay) => match replay.ticks.get(played) {
+					Some(tick) => tick.clone(),
+					None => {
+						println!("replay finished after {} ticks{}", played, if desynced { ", desynced" } else { "" });
+						game_state = SceneTrans::Quit;
+						break;
+					},
+				},
+				None => {
+					let clicked = mouse.pop_front().map(|(button, state)| {
+						inputs.update_mouse(button, state);
+						button
+					});
+					Tick::new(&inputs, coords, clicked)
+				},
+			};
+			tick.apply(&mut inputs);
+
+			game_state = match game_state {
+				SceneTrans::Quit => SceneTrans::Quit,
+				SceneTrans::Menu => menu.handle_input(&inputs, tick.coords, tick.clicked, step.dt),
+				SceneTrans::Game => game.handle_input(&inputs, tick.coords, tick.clicked, step.dt),
+				SceneTrans::Editor => editor.handle_input(&inputs, tick.coords, tick.clicked, step.dt),
+			};
+
+			let checksum = game.checksum();
+			if playback.is_some() {
+				if tick.checksum != checksum && !desynced {
+					println!("replay desynced at tick {}", played);
+					desynced = true;
+				}
+				played += 1;
+			}
+			if let Some((_, ref mut replay)) = recording {
+				tick.checksum = checksum;
+				replay.ticks.push(tick);
+			}
+		}
+
 		if game_state != SceneTrans::Quit {
-			let mut target = display.draw();
-			target.clear_color(0.0, 0.0, 1.0, 1.0);
+			renderer.begin();
+			renderer.clear((0.0, 0.0, 1.0, 1.0));
 
+			let alpha = step.alpha();
 			match game_state {
 				SceneTrans::Quit => (),
-				SceneTrans::Menu => {
-					game_state = menu.handle_input(&inputs, coords, mouse, dt);
-					menu.draw(&mut target, &program, &text_system, &font);
-				},
-				SceneTrans::Game => {
-					game_state = game.handle_input(&inputs, coords, mouse, dt);
-					game.draw(&mut target, &program, &text_system, &font);
-				},
+				SceneTrans::Menu => menu.draw(&mut renderer, alpha),
+				SceneTrans::Game => game.draw(&mut renderer, alpha),
+				SceneTrans::Editor => editor.draw(&mut renderer, alpha),
 			}
 
-			target.finish().unwrap();
+			renderer.finish();
 		}
 
-		let end_time = time::precise_time_ns();
-		dt = ((end_time - start_time) as f32 / 1e6) / 60.0;
         frames += 1;
-        accum_dt += dt;
-        if accum_dt > 15.0 {
+        accum_time += elapsed;
+        if accum_time > 1.0 {
 //            println!("{}", frames);
             frames = 0;
-            accum_dt = 0.0;
+            accum_time = 0.0;
         }
 
 		if game_state == SceneTrans::Quit {
+			if let Some((ref path, ref replay)) = recording {
+				if let Err(e) = replay.save(path) {
+					println!("could not save replay to {}: {}", path, e);
+				}
+			}
 			return;
 		}
 	}
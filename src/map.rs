@@ -1,8 +1,20 @@
-use glium;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
+use autotile::AutoTiler;
+use chunk;
+use chunk::{Chunk, ChunkSource};
+use controller::ControlStyle;
 use entity::Entity;
-use tile::{Tile, TileAtlas};
-use utils::{rerange, translate};
+use fog::{Fog, Sight, EXPLORED_SHADE};
+use gen::{GridGenerator, MapGenerator};
+use player::Player;
+use props::TileProps;
+use render::Renderer;
+use spatial;
+use spatial::SpatialHash;
+use tile::{Tile, TileAtlas, EMPTY_TILE};
+use utils::{rerange, Rectangle};
 
 pub struct View {
 	pub x: f32,
@@ -20,103 +32,779 @@ impl View {
 			height: height,
 		}
 	}
+
+	/// View-relative tile coordinates under a point in normalized device coordinates,
+	/// the inverse of `matrix`.
+	pub fn pick(&self, ndc_x: f32, ndc_y: f32) -> (f32, f32) {
+		let ui_shim = 0.075;
+		let x = rerange(ndc_x, -1.0, 1.0, 0.0, self.width - 1.0);
+		let y = rerange(ndc_y - ui_shim, -1.0, 1.0 - ui_shim, 0.0, self.height - 1.0);
+		(x, y)
+	}
+
+	/// Transform for a one tile quad at view-relative coordinates.
+	pub fn matrix(&self, x: f32, y: f32) -> [[f32; 4]; 4] {
+		let ui_shim = 0.075;
+		let scaled_x = rerange(x, 0.0, self.width - 1.0, -1.0, 1.0);
+		let scaled_y = rerange(y, 0.0, self.height - 1.0, -1.0, 1.0 - ui_shim);
+
+		let tile_width = 1.0 / (self.width - 1.0);
+		let tile_height = 1.0 / (self.height - 1.0);
+
+		[
+			[1.0 * tile_width, 0.0, 0.0, 0.0],
+			[0.0, (1.0 * tile_height) - (ui_shim * (1.0 / (self.height * 2.0))), 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[scaled_x + tile_width, scaled_y + tile_height + ui_shim, 0.0, 1.0f32],
+		]
+	}
+}
+
+/// A named grid of tiles, stored as CHUNK_SIZE square chunks keyed by chunk
+/// coordinate. Layers with a negative `order` are drawn beneath entities and
+/// the player, positive ones above both, and order 0 sits between the
+/// entities and the player.
+pub struct Layer<'a> {
+	pub name: String,
+	pub chunks: HashMap<(i32, i32), Chunk<'a>>,
+	/// Chunks the map's source had nothing for, so streaming doesn't ask again.
+	pub missing: HashSet<(i32, i32)>,
+	pub visible: bool,
+	pub opacity: f32,
+	pub order: i32,
+}
+
+impl<'a> Layer<'a> {
+	/// Builds a layer from a row-major list of atlas ids covering `width` by `height` tiles from the origin.
+	pub fn new(name: &str, width: i32, height: i32, ids: Vec<u32>, order: i32, atlas: &'a TileAtlas) -> Layer<'a> {
+		let mut layer = Layer::empty(name, order);
+
+		for (index, id) in ids.into_iter().enumerate() {
+			let x = (index as i32) % width;
+			let y = (index as i32) / width;
+			if y >= height {
+				break;
+			}
+
+			let (chunk, local) = chunk::split(x, y);
+			layer.chunks.entry(chunk).or_insert_with(|| Chunk::empty(atlas)).get_mut(local.0, local.1).tex_id = id;
+		}
+
+		layer
+	}
+
+	pub fn empty(name: &str, order: i32) -> Layer<'a> {
+		Layer {
+			name: String::from(name),
+			chunks: HashMap::new(),
+			missing: HashSet::new(),
+			visible: true,
+			opacity: 1.0,
+			order: order,
+		}
+	}
+
+	pub fn get(&self, x: i32, y: i32) -> Option<&Tile<'a>> {
+		let (chunk, local) = chunk::split(x, y);
+		self.chunks.get(&chunk).map(|chunk| chunk.get(local.0, local.1))
+	}
+}
+
+/// What coordinates off the edge of a bounded map refer to. Wrapping applies
+/// to every layer; clamping and the void only to the ground layer, with the
+/// other layers empty off the edge.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EdgePolicy {
+	/// Ground reads return the nearest edge tile, writes fail.
+	Clamp,
+	/// The map repeats toroidally for reads and writes.
+	Wrap,
+	/// Ground reads return the map's void tile, writes fail.
+	Void,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapError {
+	OutOfBounds(i32, i32),
+	/// A chunk source handed back this many ids instead of CHUNK_SIZE * CHUNK_SIZE.
+	BadChunk(usize),
+	NoEntity(usize),
+}
+
+impl fmt::Display for MapError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MapError::OutOfBounds(x, y) => write!(f, "({}, {}) is outside of the map", x, y),
+			MapError::NoEntity(index) => write!(f, "there is no entity {}", index),
+			MapError::BadChunk(len) => write!(f, "chunk has {} tiles instead of {}", len, chunk::CHUNK_SIZE * chunk::CHUNK_SIZE),
+		}
+	}
+}
+
+fn wrap(n: i32, size: i32) -> i32 {
+	let r = n % size;
+	if r < 0 { r + size } else { r }
 }
 
 pub struct Map<'a> {
-	pub tile_map: Vec<Tile<'a>>,
-	pub entity_map: Vec<Entity<'a>>,
+	pub layers: Vec<Layer<'a>>,
+	pub ground: usize,
+	/// Entities in draw order, read through `entities` and changed through
+	/// `add_entity`, `insert_entity`, `remove_entity` and `move_entity` so
+	/// `entity_index` stays in step.
+	entity_map: Vec<Entity<'a>>,
+	entity_index: SpatialHash,
 	pub view: View,
 	pub height: i32,
 	pub width: i32,
+	pub bounded: bool,
+	pub edge: EdgePolicy,
+	/// Ground tile read off the edge under `EdgePolicy::Void`.
+	pub void: Tile<'a>,
+	/// Tile read off the edge on layers other than the ground, unless the map wraps.
+	empty: Tile<'a>,
+	pub fog: Option<Fog>,
+	/// How the map is meant to be played.
+	pub style: ControlStyle,
+	pub source: Option<Box<ChunkSource>>,
+	pub autotiler: Option<AutoTiler>,
+	pub atlas: &'a TileAtlas,
 }
 
 impl<'a> Map<'a> {
 	pub fn new(width: i32, height: i32, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Map<'a> {
-		let mut tile_map = Vec::with_capacity((width * height) as usize);
-		let mut entity_map = Vec::new();
+		let mut map = Map::generate(width, height, &GridGenerator::new(), 0, view_width, ratio, atlas);
+		map.add_entity(Entity::new(1.0, 1.0, 14, atlas));
+		map
+	}
 
-		for index in 0..tile_map.capacity() {
-			let x = (index as i32) % width;
-			let y = (index as i32) / width;
-			let id;
-			if (x % 5) == 0 || (y % 5) == 0 {
-				id = 10;
-			} else {
-				id = 9;
-			}
-			tile_map.push(Tile::new(id, atlas));
-		}
+	/// A map whose ground layer comes from `generator`, with empty background,
+	/// foreground and hidden collision layers around it.
+	pub fn generate(width: i32, height: i32, generator: &MapGenerator, seed: u64, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Map<'a> {
+		let ids = generator.generate(width, height, seed);
 
-		entity_map.push(Entity::new(1.0, 1.0, 14, atlas));
+		let mut map = Map::from_ids(width, height, ids, Vec::new(), view_width, ratio, atlas);
+		map.add_layer("background", -2);
+		map.add_layer("foreground", 1);
+		let collision = map.add_layer("collision", 2);
+		map.layers[collision].visible = false;
+		map
+	}
+
+	/// Builds a single layer map from a row-major list of atlas ids, with row 0 at the bottom of the world.
+	pub fn from_ids(width: i32, height: i32, ids: Vec<u32>, entity_map: Vec<Entity<'a>>, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Map<'a> {
+		let ground = Layer::new("ground", width, height, ids, -1, atlas);
+		Map::from_layers(width, height, vec![ground], 0, entity_map, view_width, ratio, atlas)
+	}
 
+	pub fn from_layers(width: i32, height: i32, layers: Vec<Layer<'a>>, ground: usize, entity_map: Vec<Entity<'a>>, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Map<'a> {
 		let view = View::new(0.0, 0.0, view_width, ((30.0 / ratio).floor()));
+		let mut entity_index = SpatialHash::new();
+		entity_index.rebuild(&entity_map);
 
 		Map {
-			tile_map: tile_map,
+			layers: layers,
+			ground: ground,
 			entity_map: entity_map,
+			entity_index: entity_index,
 			view: view,
 			height: height,
 			width: width,
+			bounded: true,
+			edge: EdgePolicy::Void,
+			void: Tile::new(EMPTY_TILE, atlas),
+			empty: Tile::new(EMPTY_TILE, atlas),
+			fog: None,
+			style: ControlStyle::Platformer,
+			source: None,
+			autotiler: None,
+			atlas: atlas,
 		}
 	}
 
+	/// An unbounded map whose chunks are pulled from `source` as the view
+	/// moves over them. `layers` gives each layer's name and draw order.
+	pub fn streaming(layers: &[(&str, i32)], ground: usize, source: Box<ChunkSource>, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Map<'a> {
+		let layers = layers.iter().map(|&(name, order)| Layer::empty(name, order)).collect();
+		let mut map = Map::from_layers(0, 0, layers, ground, Vec::new(), view_width, ratio, atlas);
+		map.bounded = false;
+		map.source = Some(source);
+		map.stream();
+		map
+	}
+
+	/// Appends an empty layer and returns its index.
+	pub fn add_layer(&mut self, name: &str, order: i32) -> usize {
+		let layer = if self.bounded {
+			Layer::new(name, self.width, self.height, vec![EMPTY_TILE; self.size() as usize], order, self.atlas)
+		} else {
+			Layer::empty(name, order)
+		};
+		self.layers.push(layer);
+		self.layers.len() - 1
+	}
+
+	pub fn layer_index(&self, name: &str) -> Option<usize> {
+		self.layers.iter().position(|layer| layer.name == name)
+	}
+
+	pub fn layer(&self, name: &str) -> Option<&Layer<'a>> {
+		self.layers.iter().find(|layer| layer.name == name)
+	}
+
+	pub fn layer_mut(&mut self, name: &str) -> Option<&mut Layer<'a>> {
+		self.layers.iter_mut().find(|layer| layer.name == name)
+	}
+
+	pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+		!self.bounded || (x >= 0 && y >= 0 && x < self.width && y < self.height)
+	}
+
+	/// Sets how coordinates off the edge are treated, and the tile read there under `EdgePolicy::Void`.
+	pub fn set_edge(&mut self, edge: EdgePolicy, void: u32) {
+		self.edge = edge;
+		self.void = Tile::new(void, self.atlas);
+	}
+
+	/// The cell a read at (x, y) lands on under the edge policy, or None if it
+	/// lands in the void.
+	pub fn resolve(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+		if self.in_bounds(x, y) {
+			return Some((x, y));
+		}
+
+		match self.edge {
+			EdgePolicy::Clamp => Some((x.max(0).min(self.width - 1), y.max(0).min(self.height - 1))),
+			EdgePolicy::Wrap => Some((wrap(x, self.width), wrap(y, self.height))),
+			EdgePolicy::Void => None,
+		}
+	}
+
+	/// The cell a write at (x, y) lands on under the edge policy. Only wrapping
+	/// maps accept writes off the edge.
+	pub fn resolve_write(&self, x: i32, y: i32) -> Result<(i32, i32), MapError> {
+		if self.in_bounds(x, y) {
+			return Ok((x, y));
+		}
+
+		match self.edge {
+			EdgePolicy::Wrap => Ok((wrap(x, self.width), wrap(y, self.height))),
+			EdgePolicy::Clamp | EdgePolicy::Void => Err(MapError::OutOfBounds(x, y)),
+		}
+	}
+
+	/// Tile on the ground layer.
 	pub fn get(&self, x: i32, y: i32) -> Option<&Tile> {
-		let tile = self.tile_map.get(translate(x, y, self.width));
-		return tile;
+		self.get_layer(self.ground, x, y)
+	}
+
+	/// Sets a tile on the ground layer.
+	pub fn set(&mut self, x: i32, y: i32, id: u32) -> Result<(), MapError> {
+		let ground = self.ground;
+		self.set_layer(ground, x, y, id)
+	}
+
+	/// Tile at signed world coordinates under the edge policy. None means an
+	/// unloaded chunk of a streaming map.
+	pub fn get_layer(&self, layer: usize, x: i32, y: i32) -> Option<&Tile> {
+		if layer != self.ground && self.edge != EdgePolicy::Wrap && !self.in_bounds(x, y) {
+			return Some(&self.empty);
+		}
+
+		match self.resolve(x, y) {
+			Some((x, y)) => self.layers[layer].get(x, y),
+			None => Some(&self.void),
+		}
+	}
+
+	/// Sets a tile at signed world coordinates and re-picks the autotiled variants around it.
+	pub fn set_layer(&mut self, layer: usize, x: i32, y: i32, id: u32) -> Result<(), MapError> {
+		let (x, y) = try!(self.resolve_write(x, y));
+		try!(self.set_layer_raw(layer, x, y, id));
+
+		if let Some(autotiler) = self.autotiler.take() {
+			autotiler.apply_around(self, layer, x, y);
+			self.autotiler = Some(autotiler);
+		}
+		Ok(())
 	}
 
-	pub fn set(&mut self, x: i32, y: i32, id: u32) {
-		self.tile_map.get_mut(translate(x, y, self.width)).unwrap().tex_id = id;
+	/// Installs autotiling rules and applies them to the whole map.
+	pub fn set_autotiler(&mut self, autotiler: AutoTiler) {
+		autotiler.apply_all(self);
+		self.autotiler = Some(autotiler);
+	}
+
+	/// Coordinates of every cell of `layer` that is currently loaded.
+	pub fn loaded_cells(&self, layer: usize) -> Vec<(i32, i32)> {
+		let mut cells = Vec::new();
+		for key in self.layers[layer].chunks.keys() {
+			for local_y in 0..chunk::CHUNK_SIZE {
+				for local_x in 0..chunk::CHUNK_SIZE {
+					let x = key.0 * chunk::CHUNK_SIZE + local_x;
+					let y = key.1 * chunk::CHUNK_SIZE + local_y;
+					if self.in_bounds(x, y) {
+						cells.push((x, y));
+					}
+				}
+			}
+		}
+		cells
+	}
+
+	/// Changes an already loaded tile without marking its chunk edited, for
+	/// changes that can be worked out again from the chunk source, such as
+	/// autotiling a streamed chunk. Unloaded cells are left alone.
+	pub fn restyle_layer(&mut self, layer: usize, x: i32, y: i32, id: u32) {
+		let (key, local) = chunk::split(x, y);
+		if let Some(chunk) = self.layers[layer].chunks.get_mut(&key) {
+			chunk.get_mut(local.0, local.1).tex_id = id;
+		}
+	}
+
+	/// Sets a tile at signed world coordinates, loading its chunk first if needed, without autotiling.
+	pub fn set_layer_raw(&mut self, layer: usize, x: i32, y: i32, id: u32) -> Result<(), MapError> {
+		let (x, y) = try!(self.resolve_write(x, y));
+
+		let (key, local) = chunk::split(x, y);
+		let atlas = self.atlas;
+		let source = &mut self.source;
+		if !self.layers[layer].chunks.contains_key(&key) {
+			let ids = match *source {
+				Some(ref mut source) => source.load(layer, key.0, key.1),
+				None => None,
+			};
+			let chunk = match ids {
+				Some(ids) => try!(Chunk::new(ids, atlas)),
+				None => Chunk::empty(atlas),
+			};
+			self.layers[layer].missing.remove(&key);
+			self.layers[layer].chunks.insert(key, chunk);
+		}
+		let chunk = self.layers[layer].chunks.get_mut(&key).unwrap();
+		chunk.get_mut(local.0, local.1).tex_id = id;
+		chunk.dirty = true;
+		Ok(())
+	}
+
+	/// Properties of the ground tile at (x, y).
+	pub fn props(&self, x: i32, y: i32) -> Option<&TileProps> {
+		self.get(x, y).and_then(|tile| tile.props())
+	}
+
+	pub fn props_layer(&self, layer: usize, x: i32, y: i32) -> Option<&TileProps> {
+		self.get_layer(layer, x, y).and_then(|tile| tile.props())
+	}
+
+	/// True if a tile on any layer at (x, y) is solid.
+	pub fn is_solid(&self, x: i32, y: i32) -> bool {
+		(0..self.layers.len()).any(|layer| self.props_layer(layer, x, y).map_or(false, |props| props.solid))
+	}
+
+	/// True if a tile on any layer at (x, y) is opaque. Unloaded cells and
+	/// cells off the edge of a map that doesn't wrap block sight too.
+	pub fn blocks_sight(&self, x: i32, y: i32) -> bool {
+		if self.resolve_write(x, y).is_err() {
+			return true;
+		}
+		(0..self.layers.len()).any(|layer| match self.get_layer(layer, x, y) {
+			Some(tile) => tile.props().map_or(false, |props| props.opaque),
+			None => true,
+		})
+	}
+
+	/// True if a tile on any layer at (x, y) carries `tag`.
+	pub fn has_tag(&self, x: i32, y: i32, tag: &str) -> bool {
+		(0..self.layers.len()).any(|layer| self.props_layer(layer, x, y).map_or(false, |props| props.has_tag(tag)))
 	}
 
 	pub fn size(&self) -> i32 {
 		self.width * self.height
 	}
 
-	pub fn draw(&mut self, mut target: &mut glium::Frame, program: &glium::Program) {
+	/// Range of chunk coordinates covering the view, grown by `margin` chunks on every side.
+	fn view_chunks(&self, margin: i32) -> ((i32, i32), (i32, i32)) {
+		let (min, _) = chunk::split(self.view.x.floor() as i32, self.view.y.floor() as i32);
+		let (max, _) = chunk::split((self.view.x + self.view.width).ceil() as i32, (self.view.y + self.view.height).ceil() as i32);
+		((min.0 - margin, min.1 - margin), (max.0 + margin, max.1 + margin))
+	}
+
+	/// Loads the chunks around the view from the map's source and unloads
+	/// distant ones, handing edited chunks back to the source. Fresh chunks
+	/// and the seams along their neighbours are autotiled. Does nothing for
+	/// maps without a source.
+	pub fn stream(&mut self) {
+		let loaded = self.stream_chunks();
+
+		if let Some(autotiler) = self.autotiler.take() {
+			for (layer, key) in loaded {
+				autotiler.apply_chunk(self, layer, key.0, key.1);
+			}
+			self.autotiler = Some(autotiler);
+		}
+	}
+
+	/// Does the loading and unloading for `stream`, returning the layer and key of each chunk loaded.
+	fn stream_chunks(&mut self) -> Vec<(usize, (i32, i32))> {
+		let (load_min, load_max) = self.view_chunks(chunk::LOAD_MARGIN);
+		let (keep_min, keep_max) = self.view_chunks(chunk::UNLOAD_MARGIN);
+		let atlas = self.atlas;
+		let mut loaded = Vec::new();
+
+		let source = match self.source {
+			Some(ref mut source) => source,
+			None => { return loaded; },
+		};
+
+		for (index, layer) in self.layers.iter_mut().enumerate() {
+			for chunk_x in load_min.0..(load_max.0 + 1) {
+				for chunk_y in load_min.1..(load_max.1 + 1) {
+					if layer.chunks.contains_key(&(chunk_x, chunk_y)) || layer.missing.contains(&(chunk_x, chunk_y)) {
+						continue;
+					}
+					match source.load(index, chunk_x, chunk_y).map(|ids| Chunk::new(ids, atlas)) {
+						Some(Ok(chunk)) => {
+							layer.chunks.insert((chunk_x, chunk_y), chunk);
+							loaded.push((index, (chunk_x, chunk_y)));
+						},
+						Some(Err(e)) => {
+							println!("refusing chunk ({}, {}) of layer '{}': {}", chunk_x, chunk_y, layer.name, e);
+							layer.missing.insert((chunk_x, chunk_y));
+						},
+						None => { layer.missing.insert((chunk_x, chunk_y)); },
+					}
+				}
+			}
+
+			let distant: Vec<(i32, i32)> = layer.chunks.keys()
+				.filter(|key| key.0 < keep_min.0 || key.1 < keep_min.1 || key.0 > keep_max.0 || key.1 > keep_max.1)
+				.cloned()
+				.collect();
+			for key in distant {
+				let chunk = layer.chunks.remove(&key).unwrap();
+				if chunk.dirty {
+					source.unload(index, key.0, key.1, &chunk.ids());
+				}
+			}
+			layer.missing.retain(|key| key.0 >= keep_min.0 && key.1 >= keep_min.1 && key.0 <= keep_max.0 && key.1 <= keep_max.1);
+		}
+
+		loaded
+	}
+
+	fn draw_layer(&self, index: usize, renderer: &mut Renderer) {
+		let layer = &self.layers[index];
+		if !layer.visible {
+			return;
+		}
+
 		for x in 0..(self.view.width as u32) {
 			for y in 0..(self.view.height as u32) {
 				let x = x as f32;
 				let y = y as f32;
 
-				let ui_shim = 0.075;
-				let scaled_x = rerange(x, 0.0, self.view.width - 1.0, -1.0, 1.0);
-				let scaled_y = rerange(y, 0.0, self.view.height - 1.0, -1.0, 1.0 - ui_shim);
-
-				let tile_width = 1.0 / (self.view.width - 1.0);
-				let tile_height = 1.0 / (self.view.height - 1.0);
-
-				let matrix = [
-					[1.0 * tile_width, 0.0, 0.0, 0.0],
-					[0.0, (1.0 * tile_height) - (ui_shim * (1.0 / (self.view.height * 2.0))), 0.0, 0.0],
-					[0.0, 0.0, 1.0, 0.0],
-					[scaled_x + tile_width, scaled_y + tile_height + ui_shim, 0.0, 1.0f32],
-				];
-				let tile = self.tile_map.get(translate((self.view.x + x) as i32, (self.view.y + y) as i32, self.width)).unwrap();
-				tile.draw(target, &program, matrix);
+				let world_x = (self.view.x + x).floor() as i32;
+				let world_y = (self.view.y + y).floor() as i32;
+
+				if let Some(tile) = self.get_layer(index, world_x, world_y) {
+					let matrix = self.view.matrix(x, y);
+					tile.draw(renderer, matrix, layer.opacity);
+				}
 			}
 		}
+	}
 
-		for entity in self.entity_map.iter() {
-			let x = entity.x;
-			let y = entity.y;
+	/// Blacks out unexplored cells and darkens explored ones out of sight.
+	fn draw_fog(&self, renderer: &mut Renderer) {
+		if self.fog.is_none() {
+			return;
+		}
 
-			if x <= self.view.width && y <= self.view.height {
-				let ui_shim = 0.075;
-				let scaled_x = rerange(x, 0.0, self.view.width - 1.0, -1.0, 1.0);
-				let scaled_y = rerange(y, 0.0, self.view.height - 1.0, -1.0, 1.0 - ui_shim);
+		for x in 0..(self.view.width as u32) {
+			for y in 0..(self.view.height as u32) {
+				let x = x as f32;
+				let y = y as f32;
 
-				let tile_width = 1.0 / (self.view.width - 1.0);
-				let tile_height = 1.0 / (self.view.height - 1.0);
+				let world_x = (self.view.x + x).floor() as i32;
+				let world_y = (self.view.y + y).floor() as i32;
 
-				let matrix = [
-					[1.0 * tile_width, 0.0, 0.0, 0.0],
-					[0.0, (1.0 * tile_height) - (ui_shim * (1.0 / (self.view.height * 2.0))), 0.0, 0.0],
-					[0.0, 0.0, 1.0, 0.0],
-					[scaled_x + tile_width, scaled_y + tile_height + ui_shim, 0.0, 1.0f32],
-				];
-				entity.draw(target, &program, matrix);
+				let opacity = match self.sight(world_x, world_y) {
+					Sight::Visible => { continue; },
+					Sight::Explored => EXPLORED_SHADE,
+					Sight::Unexplored => 1.0,
+				};
+				renderer.draw_quad(self.view.matrix(x, y), (0.0, 0.0, 0.0, opacity));
 			}
 		}
 	}
+
+	/// Draws the layers in order, with the entities and the player slotted in at
+	/// order 0. Under fog of war, entities out of sight are left out and the
+	/// fog is drawn over everything. Moving things are drawn `alpha` of the
+	/// way from where they started the tick to where they are now.
+	pub fn draw(&mut self, renderer: &mut Renderer, player: Option<&mut Player>, alpha: f32) {
+		let mut order: Vec<usize> = (0..self.layers.len()).collect();
+		order.sort_by_key(|i| self.layers[*i].order);
+
+		for i in order.iter() {
+			if self.layers[*i].order < 0 {
+				self.draw_layer(*i, renderer);
+			}
+		}
+
+		let near_view = Rectangle::new(self.view.x - 1.0, self.view.y - 1.0, self.view.width + 2.0, self.view.height + 2.0);
+		for index in self.entities_in(&near_view) {
+			let entity = &self.entity_map[index];
+			let (x, y) = entity.lerp(alpha);
+			let x = x - self.view.x;
+			let y = y - self.view.y;
+
+			let visible = self.sight(entity.x.floor() as i32, entity.y.floor() as i32) == Sight::Visible;
+			if visible && x >= -1.0 && y >= -1.0 && x <= self.view.width && y <= self.view.height {
+				let matrix = self.view.matrix(x, y);
+				entity.draw(renderer, matrix);
+			}
+		}
+
+		for i in order.iter() {
+			if self.layers[*i].order == 0 {
+				self.draw_layer(*i, renderer);
+			}
+		}
+
+		if let Some(player) = player {
+			player.draw(renderer, &self.view, alpha);
+		}
+
+		for i in order.iter() {
+			if self.layers[*i].order > 0 {
+				self.draw_layer(*i, renderer);
+			}
+		}
+
+		self.draw_fog(renderer);
+	}
+}
+
+impl<'a> Map<'a> {
+	/// Entities in draw order.
+	pub fn entities(&self) -> &[Entity<'a>] {
+		&self.entity_map
+	}
+
+	pub fn entity(&self, index: usize) -> Option<&Entity<'a>> {
+		self.entity_map.get(index)
+	}
+
+	/// Adds an entity at the end of the entity list and returns its index.
+	pub fn add_entity(&mut self, entity: Entity<'a>) -> usize {
+		let index = self.entity_map.len();
+		self.entity_index.insert(index, entity.x, entity.y);
+		self.entity_map.push(entity);
+		index
+	}
+
+	/// Inserts an entity at `index`, shifting later ones up.
+	pub fn insert_entity(&mut self, index: usize, entity: Entity<'a>) {
+		// From the end, so no two entities share an index on the way.
+		for later in (index..self.entity_map.len()).rev() {
+			let moved = &self.entity_map[later];
+			self.entity_index.renumber(later, later + 1, moved.x, moved.y);
+		}
+		self.entity_index.insert(index, entity.x, entity.y);
+		self.entity_map.insert(index, entity);
+	}
+
+	/// Removes the entity at `index`, shifting later ones down.
+	pub fn remove_entity(&mut self, index: usize) -> Entity<'a> {
+		let entity = self.entity_map.remove(index);
+		self.entity_index.remove(index, entity.x, entity.y);
+		for later in index..self.entity_map.len() {
+			let moved = &self.entity_map[later];
+			self.entity_index.renumber(later + 1, later, moved.x, moved.y);
+		}
+		entity
+	}
+
+	/// Moves an entity, keeping the spatial index up to date.
+	pub fn move_entity(&mut self, index: usize, x: f32, y: f32) {
+		let entity = &mut self.entity_map[index];
+		if spatial::cell(entity.x, entity.y) != spatial::cell(x, y) {
+			self.entity_index.remove(index, entity.x, entity.y);
+			self.entity_index.insert(index, x, y);
+		}
+		entity.x = x;
+		entity.y = y;
+	}
+
+	/// Remembers where every entity is before a simulation tick moves them.
+	pub fn begin_tick(&mut self) {
+		for entity in self.entity_map.iter_mut() {
+			entity.prev_x = entity.x;
+			entity.prev_y = entity.y;
+		}
+	}
+
+	/// Indices of entities positioned inside `area`, in list order.
+	pub fn entities_in(&self, area: &Rectangle<f32>) -> Vec<usize> {
+		let min = spatial::cell(area.x, area.y);
+		let max = spatial::cell(area.x + area.width, area.y + area.height);
+		self.entity_index.query_cells(min, max).into_iter()
+			.filter(|&i| {
+				let entity = &self.entity_map[i];
+				entity.x >= area.x && entity.y >= area.y && entity.x < area.x + area.width && entity.y < area.y + area.height
+			})
+			.collect()
+	}
+
+	/// Indices of entities within `radius` tiles of (x, y), in list order.
+	pub fn entities_near(&self, x: f32, y: f32, radius: f32) -> Vec<usize> {
+		let min = spatial::cell(x - radius, y - radius);
+		let max = spatial::cell(x + radius, y + radius);
+		self.entity_index.query_cells(min, max).into_iter()
+			.filter(|&i| {
+				let entity = &self.entity_map[i];
+				let (dx, dy) = (entity.x - x, entity.y - y);
+				dx * dx + dy * dy <= radius * radius
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	use autotile::{AutoTileRule, AutoTiler, MaskKind};
+	use chunk::{ChunkSource, CHUNK_SIZE};
+	use entity::Entity;
+	use gen::GridGenerator;
+	use map::{EdgePolicy, Map, MapError};
+	use tile::{TileAtlas, EMPTY_TILE};
+	use utils::Rectangle;
+
+	/// Hands out a too-short chunk at the origin and nothing elsewhere, counting calls.
+	struct Sparse {
+		loads: Rc<Cell<usize>>,
+	}
+
+	impl ChunkSource for Sparse {
+		fn load(&mut self, _layer: usize, chunk_x: i32, chunk_y: i32) -> Option<Vec<u32>> {
+			self.loads.set(self.loads.get() + 1);
+			if (chunk_x, chunk_y) == (0, 0) { Some(vec![11; 3]) } else { None }
+		}
+	}
+
+	#[test]
+	fn short_chunks_are_refused() {
+		let atlas = TileAtlas::new(16, 16);
+		let loads = Rc::new(Cell::new(0));
+		let mut map = Map::streaming(&[("ground", 0)], 0, Box::new(Sparse { loads: loads.clone() }), 10.0, 1.0, &atlas);
+
+		assert!(map.get(5, 5).is_none());
+		assert_eq!(map.set_layer_raw(0, 5, 5, 9), Err(MapError::BadChunk(3)));
+		assert!(map.get(5, 5).is_none());
+	}
+
+	#[test]
+	fn missing_chunks_are_not_requested_again() {
+		let atlas = TileAtlas::new(16, 16);
+		let loads = Rc::new(Cell::new(0));
+		let mut map = Map::streaming(&[("ground", 0)], 0, Box::new(Sparse { loads: loads.clone() }), 10.0, 1.0, &atlas);
+		let first = loads.get();
+		assert!(first > 1);
+
+		map.stream();
+		map.stream();
+		assert_eq!(loads.get(), first);
+	}
+
+	/// A strip of terrain three chunks long along the bottom of the world.
+	struct Strip;
+
+	impl ChunkSource for Strip {
+		fn load(&mut self, _layer: usize, chunk_x: i32, chunk_y: i32) -> Option<Vec<u32>> {
+			if chunk_y == 0 && chunk_x >= 0 && chunk_x < 3 {
+				Some(vec![0; (CHUNK_SIZE * CHUNK_SIZE) as usize])
+			} else {
+				None
+			}
+		}
+	}
+
+	#[test]
+	fn streamed_chunks_are_autotiled_across_the_seam() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = Map::streaming(&[("ground", 0)], 0, Box::new(Strip), 10.0, 1.0, &atlas);
+		let mut rule = AutoTileRule::new(MaskKind::Cardinal, (0..16).collect());
+		rule.connect_edges = false;
+		map.set_autotiler(AutoTiler::new(vec![rule]));
+		assert_eq!(map.get(63, 5).map(|tile| tile.tex_id), Some(1 | 4 | 8));
+
+		map.view.x += 40.0;
+		map.stream();
+		assert_eq!(map.get(63, 5).map(|tile| tile.tex_id), Some(15));
+		assert_eq!(map.get(64, 5).map(|tile| tile.tex_id), Some(15));
+		assert_eq!(map.get(95, 5).map(|tile| tile.tex_id), Some(1 | 4 | 8));
+		assert!(map.layers[0].chunks.values().all(|chunk| !chunk.dirty));
+
+		map.set(70, 5, 0).unwrap();
+		assert!(map.layers[0].chunks[&(2, 0)].dirty);
+		assert!(!map.layers[0].chunks[&(0, 0)].dirty);
+	}
+
+	#[test]
+	fn edge_policy_only_reaches_past_the_ground_when_wrapping() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = Map::generate(10, 10, &GridGenerator::new(), 0, 10.0, 1.0, &atlas);
+		let decor = map.add_layer("decor", 1);
+		map.set(0, 4, 9).unwrap();
+		map.set_layer(decor, 0, 4, 14).unwrap();
+
+		map.set_edge(EdgePolicy::Clamp, EMPTY_TILE);
+		assert_eq!(map.get(-3, 4).map(|tile| tile.tex_id), Some(9));
+		assert_eq!(map.get_layer(decor, -3, 4).map(|tile| tile.tex_id), Some(EMPTY_TILE));
+
+		map.set_edge(EdgePolicy::Void, 8);
+		assert_eq!(map.get(-3, 4).map(|tile| tile.tex_id), Some(8));
+		assert_eq!(map.get_layer(decor, -3, 4).map(|tile| tile.tex_id), Some(EMPTY_TILE));
+
+		map.set_edge(EdgePolicy::Wrap, EMPTY_TILE);
+		assert_eq!(map.get_layer(decor, 10, 4).map(|tile| tile.tex_id), Some(14));
+	}
+
+	#[test]
+	fn entity_queries_follow_inserts_and_removals() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = Map::generate(10, 10, &GridGenerator::new(), 0, 10.0, 1.0, &atlas);
+		map.add_entity(Entity::new(1.0, 1.0, 1, &atlas));
+		map.add_entity(Entity::new(1.5, 1.5, 2, &atlas));
+		map.add_entity(Entity::new(5.0, 5.0, 3, &atlas));
+
+		map.insert_entity(0, Entity::new(1.2, 1.2, 4, &atlas));
+		map.insert_entity(2, Entity::new(8.0, 8.0, 5, &atlas));
+		let ids = |map: &Map, found: Vec<usize>| found.into_iter().map(|i| map.entity(i).unwrap().tex_id).collect::<Vec<u32>>();
+		let corner = Rectangle::new(0.0, 0.0, 3.0, 3.0);
+		assert_eq!(ids(&map, map.entities_in(&corner)), vec![4, 1, 2]);
+		assert_eq!(ids(&map, map.entities_near(8.0, 8.0, 0.5)), vec![5]);
+
+		assert_eq!(map.remove_entity(1).tex_id, 1);
+		assert_eq!(ids(&map, map.entities_in(&corner)), vec![4, 2]);
+		assert_eq!(ids(&map, map.entities_near(5.0, 5.0, 4.5)), vec![5, 3]);
+		assert_eq!(map.entities().iter().map(|entity| entity.tex_id).collect::<Vec<u32>>(), vec![4, 5, 2, 3]);
+	}
+
+	#[test]
+	fn entity_queries_take_huge_areas() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = Map::generate(10, 10, &GridGenerator::new(), 0, 10.0, 1.0, &atlas);
+		map.add_entity(Entity::new(1.0, 1.0, 1, &atlas));
+		map.add_entity(Entity::new(9.5, 0.5, 2, &atlas));
+
+		assert_eq!(map.entities_near(5.0, 5.0, f32::MAX), vec![0, 1]);
+		assert_eq!(map.entities_near(0.0, 0.0, 3.0e9), vec![0, 1]);
+		assert_eq!(map.entities_in(&Rectangle::new(-f32::MAX, -f32::MAX, f32::MAX, f32::MAX)), Vec::<usize>::new());
+		assert_eq!(map.entities_in(&Rectangle::new(-1.0e10, -1.0e10, 2.0e10, 2.0e10)), vec![0, 1]);
+	}
 }
@@ -1,57 +1,45 @@
 use std::fs::File;
+use std::io::BufReader;
 
 use image;
-use glium;
-use glium::Surface;
-use glium_text;
 
 use Scene;
 use SceneTrans;
-use vert::Vert;
+use render::{Renderer, TextureId, IDENTITY};
 use utils::rerange;
 use keyboard;
+use keyboard::MouseButton;
 
 pub struct Menu {
-    pub tex: glium::texture::SrgbTexture2d,
-    pub buffer: glium::VertexBuffer<Vert>,
+    pub tex: TextureId,
     pub buttons: Vec<Button>,
     pub ratio: f32,
 }
 
 impl Menu {
-    pub fn new(display: &glium::backend::glutin_backend::GlutinFacade, tex_name: &str, ratio: f32) -> Menu {
+    pub fn new(renderer: &mut Renderer, tex_name: &str, ratio: f32) -> Menu {
         let tex_file = File::open(tex_name).unwrap();
-        let img = image::load(tex_file, image::PNG).unwrap().to_rgba();
-        let dims = img.dimensions();
-        let raw_img = glium::texture::RawImage2d::from_raw_rgba_reversed(img.into_raw(), dims);
-        let texture = glium::texture::SrgbTexture2d::new(display, raw_img).unwrap();
-
-        let vert1 = Vert { position: [-1.0, -1.0], tex_coords: [ 0.0, 0.0] };
-        let vert2 = Vert { position: [-1.0,  1.0], tex_coords: [ 0.0, 1.0] };
-        let vert3 = Vert { position: [ 1.0, -1.0], tex_coords: [ 1.0, 0.0] };
-        let vert4 = Vert { position: [ 1.0, -1.0], tex_coords: [ 1.0, 0.0] };
-        let vert5 = Vert { position: [-1.0,  1.0], tex_coords: [ 0.0, 1.0] };
-        let vert6 = Vert { position: [ 1.0,  1.0], tex_coords: [ 1.0, 1.0] };
-        let verts = [vert1, vert2, vert3, vert4, vert5, vert6];
-        let buffer = glium::VertexBuffer::immutable(display, &verts).unwrap();
+        let img = image::load(BufReader::new(tex_file), image::PNG).unwrap().to_rgba();
+        let texture = renderer.load_texture(img);
 
         let mut buttons = Vec::new();
-        let start_button = Button::new(display, -0.25, -0.4, 0.3, 0.2, String::from("Start"), String::from("assets/button.png"), ratio);
-        let quit_button = Button::new(display, 0.25, -0.4, 0.3, 0.2, String::from("Quit"), String::from("assets/button.png"), ratio);
+        let start_button = Button::new(renderer, -0.25, -0.4, 0.3, 0.2, String::from("Start"), String::from("assets/button.png"), ratio);
+        let quit_button = Button::new(renderer, 0.25, -0.4, 0.3, 0.2, String::from("Quit"), String::from("assets/button.png"), ratio);
+        let edit_button = Button::new(renderer, 0.0, -0.7, 0.3, 0.2, String::from("Edit"), String::from("assets/button.png"), ratio);
         buttons.push(start_button);
         buttons.push(quit_button);
+        buttons.push(edit_button);
 
         Menu {
             tex: texture,
-            buffer: buffer,
             buttons: buttons,
             ratio: ratio,
         }
     }
 }
 
-impl<'a> Scene<'a> for Menu {
-    fn handle_input(&mut self, inputs: &keyboard::Inputs, coords: Option<(i32, i32)>, clicked: Option<glium::glutin::MouseButton>, dt: f32) -> SceneTrans {
+impl Scene for Menu {
+    fn handle_input(&mut self, inputs: &keyboard::Inputs, coords: Option<(i32, i32)>, clicked: Option<MouseButton>, dt: f32) -> SceneTrans {
         let mut state = SceneTrans::Menu;
         if coords.is_some() {
             let coords = coords.unwrap();
@@ -61,7 +49,7 @@ impl<'a> Scene<'a> for Menu {
 
             for button in self.buttons.iter() {
                 if button.is_hovered(x, y) {
-                    if clicked.is_some() && clicked.unwrap() == glium::glutin::MouseButton::Left {
+                    if clicked.is_some() && clicked.unwrap() == MouseButton::Left {
                         state = button.trigger();
                     }
                 }
@@ -80,22 +68,10 @@ impl<'a> Scene<'a> for Menu {
         return state;
     }
 
-    fn draw(&mut self, mut target: &mut glium::Frame, program: &glium::Program, text_system: &glium_text::TextSystem, font: &glium_text::FontTexture) {
-        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-
-        let uniform = uniform! {
-            matrix: [
-                [1.0, 0.0, 0.0, 0.0],
-                [0.0, 1.0, 0.0, 0.0],
-                [0.0, 0.0, 1.0, 0.0],
-                [0.0, 0.0, 0.0, 1.0f32],
-            ],
-            tex: self.tex.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-        };
-
-        target.draw(&self.buffer, &indices, program, &uniform, &Default::default()).unwrap();
+    fn draw(&mut self, renderer: &mut Renderer, _alpha: f32) {
+        renderer.draw_texture(self.tex, IDENTITY, 1.0);
         for button in self.buttons.iter() {
-            button.draw(&mut target, program, text_system, font);
+            button.draw(renderer);
         }
     }
 }
@@ -107,26 +83,14 @@ pub struct Button {
     height: f32,
     text: String,
     ratio: f32,
-    tex: glium::texture::SrgbTexture2d,
-    buffer: glium::VertexBuffer<Vert>,
+    tex: TextureId,
 }
 
 impl Button {
-    pub fn new(display: &glium::backend::glutin_backend::GlutinFacade, x: f32, y: f32, width: f32, height: f32, text: String, tex_name: String, ratio: f32) -> Button {
+    pub fn new(renderer: &mut Renderer, x: f32, y: f32, width: f32, height: f32, text: String, tex_name: String, ratio: f32) -> Button {
         let tex_file = File::open(tex_name).unwrap();
-        let img = image::load(tex_file, image::PNG).unwrap().to_rgba();
-        let dims = img.dimensions();
-        let raw_img = glium::texture::RawImage2d::from_raw_rgba_reversed(img.into_raw(), dims);
-        let texture = glium::texture::SrgbTexture2d::new(display, raw_img).unwrap();
-
-        let vert1 = Vert { position: [-1.0, -1.0], tex_coords: [ 0.0, 0.0] };
-        let vert2 = Vert { position: [-1.0,  1.0], tex_coords: [ 0.0, 1.0] };
-        let vert3 = Vert { position: [ 1.0, -1.0], tex_coords: [ 1.0, 0.0] };
-        let vert4 = Vert { position: [ 1.0, -1.0], tex_coords: [ 1.0, 0.0] };
-        let vert5 = Vert { position: [-1.0,  1.0], tex_coords: [ 0.0, 1.0] };
-        let vert6 = Vert { position: [ 1.0,  1.0], tex_coords: [ 1.0, 1.0] };
-        let verts = [vert1, vert2, vert3, vert4, vert5, vert6];
-        let buffer = glium::VertexBuffer::immutable(display, &verts).unwrap();
+        let img = image::load(BufReader::new(tex_file), image::PNG).unwrap().to_rgba();
+        let texture = renderer.load_texture(img);
 
         Button {
             x: x,
@@ -134,7 +98,6 @@ impl Button {
             width: width,
             height: height,
             text: text,
-            buffer: buffer,
             ratio: ratio,
             tex: texture,
         }
@@ -149,22 +112,19 @@ impl Button {
             return SceneTrans::Quit;
         } else if self.text.as_str() == "Start" {
             return SceneTrans::Game;
+        } else if self.text.as_str() == "Edit" {
+            return SceneTrans::Editor;
         }
         return SceneTrans::Menu;
     }
 
-    fn draw(&self, mut target: &mut glium::Frame, program: &glium::Program, text_system: &glium_text::TextSystem, font: &glium_text::FontTexture) {
-        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-
-        let uniform = uniform! {
-            matrix: [
-                [1.0 * 0.1, 0.0, 0.0, 0.0],
-                [0.0, 1.0 * 0.1, 0.0, 0.0],
-                [0.0, 0.0, 1.0, 0.0],
-                [self.x, self.y, 0.0, 1.0f32],
-            ],
-            tex: self.tex.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-        };
+    fn draw(&self, renderer: &mut Renderer) {
+        let matrix = [
+            [1.0 * 0.1, 0.0, 0.0, 0.0],
+            [0.0, 1.0 * 0.1, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [self.x, self.y, 0.0, 1.0f32],
+        ];
 
         let text_matrix = [
             [0.05 / self.ratio, 0.0, 0.0, 0.0],
@@ -173,9 +133,7 @@ impl Button {
             [self.x - (self.width / 2.0), self.y - (self.height / 4.0), 0.0, 1.0],
         ];
 
-        target.draw(&self.buffer, &indices, program, &uniform, &Default::default()).unwrap();
-
-        let text = glium_text::TextDisplay::new(text_system, font, self.text.as_str());
-        glium_text::draw(&text, text_system, target, text_matrix, (1.0, 1.0, 0.0, 1.0));
+        renderer.draw_texture(self.tex, matrix, 1.0);
+        renderer.draw_text(self.text.as_str(), text_matrix, (1.0, 1.0, 0.0, 1.0));
     }
 }
@@ -0,0 +1,400 @@
+//! Grid pathfinding: A* for one agent, Dijkstra maps for many agents heading
+//! to the same goals.
+//!
+//! Paths are lists of cells in the coordinates the map's edge policy resolves
+//! them to, so a path on a wrapping map may jump from one edge to the other.
+//! Cells the map wouldn't accept a write to, such as off the edge of a
+//! clamped map, are never walked through.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::f32;
+
+use map::{EdgePolicy, Map};
+use props::TileProps;
+use tile::TileAtlas;
+
+/// Cost of stepping onto a cell, None if it can't be entered.
+pub trait Cost {
+	fn cost(&self, map: &Map, x: i32, y: i32) -> Option<f32>;
+
+	/// Lower bound of every cost returned. The A* heuristic is scaled by it,
+	/// so overstating it finds paths that aren't the cheapest.
+	fn min_cost(&self) -> f32;
+}
+
+/// A cost function paired with the lowest cost it returns.
+pub struct CostFn<F> {
+	pub cost: F,
+	pub min_cost: f32,
+}
+
+impl<F: Fn(&Map, i32, i32) -> Option<f32>> Cost for CostFn<F> {
+	fn cost(&self, map: &Map, x: i32, y: i32) -> Option<f32> {
+		(self.cost)(map, x, y)
+	}
+
+	fn min_cost(&self) -> f32 {
+		self.min_cost
+	}
+}
+
+/// Cost from tile properties. Solid and unloaded cells can't be entered,
+/// though one-way platforms can, slippery ground costs more, and each point
+/// of damage per second adds `hazard`.
+pub struct TileCost<'a> {
+	pub hazard: f32,
+	/// Whose tiles `min_cost` looks through.
+	pub atlas: &'a TileAtlas,
+}
+
+impl<'a> TileCost<'a> {
+	pub fn new(atlas: &'a TileAtlas) -> TileCost<'a> {
+		TileCost {
+			hazard: 1.0,
+			atlas: atlas,
+		}
+	}
+
+	fn tile_cost(&self, props: &TileProps) -> f32 {
+		((1.0 / props.friction.max(0.1)).max(1.0) + props.damage_per_second * self.hazard).max(0.0)
+	}
+}
+
+impl<'a> Cost for TileCost<'a> {
+	fn cost(&self, map: &Map, x: i32, y: i32) -> Option<f32> {
+		if map.get(x, y).is_none() {
+			return None;
+		}
+		let blocked = (0..map.layers.len()).any(|layer| map.props_layer(layer, x, y).map_or(false, |props| props.solid && !props.one_way));
+		if blocked {
+			return None;
+		}
+
+		let cost = match map.props(x, y) {
+			Some(props) => self.tile_cost(props),
+			None => 1.0,
+		};
+		Some(cost)
+	}
+
+	/// The cheapest enterable tile in the atlas, or 1 for cells without properties.
+	fn min_cost(&self) -> f32 {
+		self.atlas.props.iter()
+			.filter(|props| !props.solid || props.one_way)
+			.map(|props| self.tile_cost(props))
+			.fold(1.0, |a, b| a.min(b))
+	}
+}
+
+/// How a diagonal step treats the two orthogonal cells it squeezes between.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Corners {
+	/// Both must be passable.
+	NoCutting,
+	/// At least one must be passable.
+	CutOne,
+	/// Neither needs to be.
+	CutBoth,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Movement {
+	Four,
+	Eight(Corners),
+}
+
+/// A cell on the open list, ordered so `BinaryHeap` pops the cheapest first.
+struct Open {
+	priority: f32,
+	cell: (i32, i32),
+}
+
+impl PartialEq for Open {
+	fn eq(&self, other: &Open) -> bool {
+		self.priority == other.priority
+	}
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+	fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Open {
+	fn cmp(&self, other: &Open) -> Ordering {
+		other.priority.partial_cmp(&self.priority).unwrap_or(Ordering::Equal)
+	}
+}
+
+/// Movement rules and costs for searches over a map.
+pub struct Pathfinder<C: Cost> {
+	pub movement: Movement,
+	pub cost: C,
+	/// Most cells a single search will expand, so searches on streaming or
+	/// wrapping maps can't run away.
+	pub limit: usize,
+}
+
+impl<C: Cost> Pathfinder<C> {
+	pub fn new(movement: Movement, cost: C) -> Pathfinder<C> {
+		Pathfinder {
+			movement: movement,
+			cost: cost,
+			limit: 100000,
+		}
+	}
+
+	fn passable(&self, map: &Map, cell: (i32, i32)) -> bool {
+		self.cost.cost(map, cell.0, cell.1).is_some()
+	}
+
+	/// Cells reachable in one step from `cell`, with the length of the step.
+	fn neighbours(&self, map: &Map, cell: (i32, i32)) -> Vec<((i32, i32), f32)> {
+		let (x, y) = cell;
+		let mut steps = Vec::with_capacity(8);
+
+		for &(dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+			steps.push(((x + dx, y + dy), 1.0));
+		}
+
+		if let Movement::Eight(corners) = self.movement {
+			for &(dx, dy) in [(1, 1), (1, -1), (-1, 1), (-1, -1)].iter() {
+				let open = [(x + dx, y), (x, y + dy)].iter().filter(|&&side| self.passable(map, side)).count();
+				let allowed = match corners {
+					Corners::NoCutting => open == 2,
+					Corners::CutOne => open >= 1,
+					Corners::CutBoth => true,
+				};
+				if allowed {
+					steps.push(((x + dx, y + dy), f32::consts::SQRT_2));
+				}
+			}
+		}
+
+		steps.into_iter()
+			.filter_map(|(next, length)| map.resolve_write(next.0, next.1).ok().map(|next| (next, length)))
+			.filter(|&(next, _)| self.passable(map, next))
+			.collect()
+	}
+
+	/// Lower bound of the cost from `a` to `b`. Wrapping maps lose their
+	/// heuristic, as the shortest way round isn't known without searching.
+	fn heuristic(&self, map: &Map, a: (i32, i32), b: (i32, i32)) -> f32 {
+		if map.bounded && map.edge == EdgePolicy::Wrap {
+			return 0.0;
+		}
+
+		let dx = (a.0 - b.0).abs() as f32;
+		let dy = (a.1 - b.1).abs() as f32;
+		let distance = match self.movement {
+			Movement::Four => dx + dy,
+			Movement::Eight(_) => dx.max(dy) + (f32::consts::SQRT_2 - 1.0) * dx.min(dy),
+		};
+		distance * self.cost.min_cost()
+	}
+
+	/// Cheapest path from `start` to `goal`, both included, found with A*.
+	/// None if the goal can't be reached within `limit` expanded cells.
+	pub fn find_path(&self, map: &Map, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+		let start = match map.resolve_write(start.0, start.1) {
+			Ok(start) => start,
+			Err(_) => { return None; },
+		};
+		let goal = match map.resolve_write(goal.0, goal.1) {
+			Ok(goal) => goal,
+			Err(_) => { return None; },
+		};
+		if !self.passable(map, goal) {
+			return None;
+		}
+
+		let mut open = BinaryHeap::new();
+		let mut came_from = HashMap::new();
+		let mut spent = HashMap::new();
+		open.push(Open { priority: self.heuristic(map, start, goal), cell: start });
+		spent.insert(start, 0.0);
+
+		let mut expanded = 0;
+		while let Some(Open { cell, priority }) = open.pop() {
+			if cell == goal {
+				let mut path = vec![goal];
+				let mut cell = goal;
+				while let Some(&prev) = came_from.get(&cell) {
+					path.push(prev);
+					cell = prev;
+				}
+				path.reverse();
+				return Some(path);
+			}
+
+			let here = spent[&cell];
+			if priority > here + self.heuristic(map, cell, goal) {
+				// A stale entry for a cell since reached more cheaply.
+				continue;
+			}
+
+			expanded += 1;
+			if expanded > self.limit {
+				return None;
+			}
+
+			for (next, length) in self.neighbours(map, cell) {
+				let cost = here + length * self.cost.cost(map, next.0, next.1).unwrap();
+				if spent.get(&next).map_or(true, |&known| cost < known) {
+					spent.insert(next, cost);
+					came_from.insert(next, cell);
+					open.push(Open { priority: cost + self.heuristic(map, next, goal), cell: next });
+				}
+			}
+		}
+		None
+	}
+
+	/// Cost of the cheapest way from every reachable cell to the nearest of
+	/// `goals`, spreading out no further than `max_cost`.
+	pub fn dijkstra(&self, map: &Map, goals: &[(i32, i32)], max_cost: f32) -> DijkstraMap {
+		let mut open = BinaryHeap::new();
+		let mut distance = HashMap::new();
+		let mut next_step = HashMap::new();
+
+		for &(x, y) in goals {
+			if let Ok(goal) = map.resolve_write(x, y) {
+				if self.passable(map, goal) {
+					distance.insert(goal, 0.0);
+					open.push(Open { priority: 0.0, cell: goal });
+				}
+			}
+		}
+
+		let mut expanded = 0;
+		while let Some(Open { cell, priority }) = open.pop() {
+			if priority > distance[&cell] {
+				continue;
+			}
+
+			expanded += 1;
+			if expanded > self.limit {
+				break;
+			}
+
+			// Searching outwards from the goals, so an agent on `next` steps onto `cell`.
+			let enter = self.cost.cost(map, cell.0, cell.1).unwrap();
+			for (next, length) in self.neighbours(map, cell) {
+				let cost = priority + length * enter;
+				if cost <= max_cost && distance.get(&next).map_or(true, |&known| cost < known) {
+					distance.insert(next, cost);
+					next_step.insert(next, cell);
+					open.push(Open { priority: cost, cell: next });
+				}
+			}
+		}
+
+		DijkstraMap {
+			distance: distance,
+			next_step: next_step,
+		}
+	}
+}
+
+/// Distances to a set of goals and the flow field leading to them, shared by
+/// every agent heading there.
+pub struct DijkstraMap {
+	distance: HashMap<(i32, i32), f32>,
+	next_step: HashMap<(i32, i32), (i32, i32)>,
+}
+
+impl DijkstraMap {
+	/// Cost from `cell` to the nearest goal, None if it wasn't reached.
+	pub fn distance(&self, cell: (i32, i32)) -> Option<f32> {
+		self.distance.get(&cell).cloned()
+	}
+
+	/// The cell to move to from `cell` to head for the nearest goal. None at a
+	/// goal or where no goal was reached.
+	pub fn next_step(&self, cell: (i32, i32)) -> Option<(i32, i32)> {
+		self.next_step.get(&cell).cloned()
+	}
+
+	/// Cheapest path from `cell` to the nearest goal, both included.
+	pub fn path(&self, cell: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+		if !self.distance.contains_key(&cell) {
+			return None;
+		}
+
+		let mut path = vec![cell];
+		let mut cell = cell;
+		while let Some(next) = self.next_step(cell) {
+			path.push(next);
+			cell = next;
+		}
+		Some(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use gen::GridGenerator;
+	use map::Map;
+	use path::{CostFn, Movement, Pathfinder, TileCost};
+	use tile::TileAtlas;
+
+	const WALL: u32 = 11;
+	const PLATFORM: u32 = 15;
+
+	fn map<'a>(atlas: &'a TileAtlas) -> Map<'a> {
+		let mut map = Map::generate(5, 5, &GridGenerator::new(), 0, 10.0, 1.0, atlas);
+		for y in 0..5 {
+			map.set(2, y, WALL).unwrap();
+		}
+		map
+	}
+
+	#[test]
+	fn walks_through_one_way_platforms() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		let finder = Pathfinder::new(Movement::Four, TileCost::new(&atlas));
+		assert_eq!(finder.find_path(&map, (0, 2), (4, 2)), None);
+
+		map.set(2, 2, PLATFORM).unwrap();
+		let path = finder.find_path(&map, (0, 2), (4, 2)).unwrap();
+		assert_eq!(path.len(), 5);
+		assert!(path.contains(&(2, 2)));
+	}
+
+	#[test]
+	fn finds_the_cheapest_path_when_steps_cost_less_than_one() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(2, 0, 0).unwrap();
+		map.set(2, 4, 0).unwrap();
+		// Stepping onto the top row is nearly free, so the long way over it
+		// beats the short way along the bottom.
+		let cost = CostFn {
+			cost: |map: &Map, x: i32, y: i32| if map.is_solid(x, y) { None } else if y == 4 { Some(0.01) } else { Some(1.0) },
+			min_cost: 0.01,
+		};
+		let finder = Pathfinder::new(Movement::Four, cost);
+		let path = finder.find_path(&map, (0, 3), (4, 3)).unwrap();
+		assert!(path.contains(&(2, 4)));
+	}
+
+	#[test]
+	fn dijkstra_maps_lead_to_the_goal() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(2, 4, 0).unwrap();
+		let finder = Pathfinder::new(Movement::Four, TileCost::new(&atlas));
+		let flow = finder.dijkstra(&map, &[(4, 0)], 100.0);
+
+		assert_eq!(flow.distance((4, 0)), Some(0.0));
+		assert_eq!(flow.next_step((4, 0)), None);
+		assert_eq!(flow.next_step((3, 0)), Some((4, 0)));
+		assert_eq!(flow.distance((0, 0)), Some(12.0));
+		assert_eq!(flow.path((0, 0)).map(|path| path.len()), Some(13));
+	}
+}
@@ -1,13 +1,9 @@
-use std::f32;
-
-use glium;
-
+use collide::{Aabb, Contacts};
+use render::Renderer;
 use tile::{Tile, TileAtlas};
 use map::View;
-use utils::rerange;
 use utils::V2;
 use map;
-use keyboard;
 
 pub enum Direction {
 	Up,
@@ -17,9 +13,16 @@ pub enum Direction {
 }
 
 pub struct Player<'a> {
+	/// Bottom-left corner in world tiles, independent of where the view is.
 	pub pos: V2<f32>,
+	/// Position at the start of the current tick, for interpolated drawing.
+	pub prev_pos: V2<f32>,
 	pub vel: V2<f32>,
 	pub dir: Direction,
+	/// Size of the collision box, whose bottom-left corner is `pos`.
+	pub size: V2<f32>,
+	/// What the last move ran into.
+	pub contacts: Contacts,
 	pub up: Tile<'a>,
 	pub down: Tile<'a>,
 	pub left: Tile<'a>,
@@ -30,8 +33,11 @@ impl<'a> Player<'a> {
 	pub fn new(dirs: Vec<u32>, atlas: &'a TileAtlas, pos: V2<f32>) -> Player<'a> {
 		Player {
 			pos: pos,
+			prev_pos: pos,
 			vel: V2::new(0.0, 0.0),
 			dir: Direction::Down,
+			size: V2::new(1.0, 1.0),
+			contacts: Contacts::default(),
 			up: Tile::new(dirs[0], atlas),
 			down: Tile::new(dirs[1], atlas),
 			left: Tile::new(dirs[2], atlas),
@@ -39,109 +45,44 @@ impl<'a> Player<'a> {
 		}
 	}
 
-	pub fn move_to(&mut self, map: &map::Map, x: f32, y: f32, dt: f32) {
-        let friction = -1.0;
-
-		let x_acc = friction * self.vel.x + x;
-		let y_acc = friction * self.vel.y + y;
-
-		self.pos.x = (0.5 * x_acc * dt * dt) + self.vel.x * dt + self.pos.x;
-		self.pos.y = (0.5 * y_acc * dt * dt) + self.vel.y * dt + self.pos.y;
-		self.vel.x = (x_acc * dt) + self.vel.x;
-		self.vel.y = (y_acc * dt) + self.vel.y;
-
-        for entity in map.entity_map.iter() {
-            if (self.pos.x) == (entity.x) {
-                self.pos.x -= 1.0;
-            }
-
-            if (self.pos.y) == (entity.y) {
-                self.pos.y -= 1.0;
-            }
-        }
-
-		if !(self.pos.x > 0.0 && self.pos.y > 0.0) {
-			if self.pos.x < 0.0 {
-				self.pos.x = 0.0;
-				self.vel.x = 0.0;
-			}
-			if self.pos.y < 0.0 {
-				self.pos.y = 0.0;
-				self.vel.y = 0.0;
-			}
+	/// Moves by (dx, dy), stopping against solid tiles and the bottom-left
+	/// edge of the world, and records what was hit in `contacts`.
+	pub fn slide(&mut self, map: &map::Map, dx: f32, dy: f32) {
+		let body = Aabb::new(self.pos.x, self.pos.y, self.size.x, self.size.y);
+		let (body, mut contacts) = map.sweep(&body, dx, dy);
+		self.pos.x = body.x;
+		self.pos.y = body.y;
+
+		if self.pos.x < 0.0 {
+			self.pos.x = 0.0;
+			contacts.wall_left = true;
+		}
+		if self.pos.y < 0.0 {
+			self.pos.y = 0.0;
+			contacts.grounded = true;
 		}
 
+		if contacts.wall_left || contacts.wall_right {
+			self.vel.x = 0.0;
+		}
+		if (contacts.grounded && self.vel.y < 0.0) || (contacts.ceiling && self.vel.y > 0.0) {
+			self.vel.y = 0.0;
+		}
+		self.contacts = contacts;
 	}
 
-    pub fn handle_input(&mut self, map: &map::Map, keys: Vec<&keyboard::Action>, t: f32) {
-        let mut mx = 0.0;
-        let mut my = -0.1;
-        for key in keys {
-            match *key {
-                keyboard::Action::Space => { my += 1.0; },
-                keyboard::Action::Left => { mx += -1.0; },
-                keyboard::Action::Right => { mx += 1.0; },
-                _ => { },
-            }
-        }
-
-        if mx > 1.0 { mx = 1.0; }
-        if mx < -1.0 { mx = -1.0; }
-        if my > 1.0 { my = 1.0; }
-        if my < -1.0 { my = -1.0; }
-
-        let diag = (f32::consts::PI / 4.0).sin();
-        if mx == 1.0 && my == 1.0 {
-            mx = diag;
-            my = diag;
-        }
-
-        if mx == 1.0 && my == -1.0 {
-            mx = diag;
-            my = -diag;
-        }
-
-        if mx == -1.0 && my == 1.0 {
-            mx = -diag;
-            my = diag;
-        }
-
-        if mx == -1.0 && my == -1.0 {
-            mx = -diag;
-            my = -diag;
-        }
-
-        if mx == 1.0 && my == 0.0 {
-            self.dir = Direction::Right;
-        }
-        if mx == -1.0 && my == 0.0 {
-            self.dir = Direction::Left;
-        }
-        if mx == 0.0 && my == -1.0 {
-            self.dir = Direction::Down;
-        }
-        if mx == 0.0 && my == 1.0 {
-            self.dir = Direction::Up;
-        }
-
-        self.move_to(map, mx, my, t);
-    }
-
-	pub fn draw(&mut self, mut target: &mut glium::Frame, program: &glium::Program, view: &View) {
-
-		let ui_shim = 0.075;
-		let scaled_x = rerange(self.pos.x, 0.0, view.width - 1.0, -1.0, 1.0);
-		let scaled_y = rerange(self.pos.y, 0.0, view.height - 1.0, -1.0, 1.0 - ui_shim);
+	/// Remembers where the player is before a simulation tick moves it.
+	pub fn begin_tick(&mut self) {
+		self.prev_pos = self.pos;
+	}
 
-		let tile_width = 1.0 / (view.width - 1.0);
-		let tile_height = 1.0 / (view.height - 1.0);
+	/// Draws the player `alpha` of the way from the start of the tick to now.
+	/// `pos` is in world tiles, like everything else on the map.
+	pub fn draw(&mut self, renderer: &mut Renderer, view: &View, alpha: f32) {
+		let x = self.prev_pos.x + (self.pos.x - self.prev_pos.x) * alpha;
+		let y = self.prev_pos.y + (self.pos.y - self.prev_pos.y) * alpha;
+		let matrix = view.matrix(x - view.x, y - view.y);
 
-		let matrix = [
-			[1.0 * tile_width, 0.0, 0.0, 0.0],
-			[0.0, (1.0 * tile_height) - (ui_shim * (1.0 / (view.height * 2.0))), 0.0, 0.0],
-			[0.0, 0.0, 1.0, 0.0],
-			[scaled_x + tile_width, scaled_y + tile_height + ui_shim, 0.0, 1.0f32],
-		];
 		let tile;
 		match self.dir {
 			Direction::Up => { tile = &self.up; },
@@ -149,6 +90,6 @@ impl<'a> Player<'a> {
 			Direction::Left => { tile = &self.left; },
 			Direction::Right => { tile = &self.right; },
 		}
-		tile.draw(&mut target, program, matrix);
+		tile.draw(renderer, matrix, 1.0);
 	}
 }
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::io;
+
+use rustc_serialize::json::{self, Json};
+
+#[derive(Debug)]
+pub enum PropsError {
+	Io(io::Error),
+	Json(json::BuilderError),
+	Malformed(String),
+	UnknownTile(u32),
+}
+
+impl fmt::Display for PropsError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			PropsError::Io(ref e) => write!(f, "io error: {}", e),
+			PropsError::Json(ref e) => write!(f, "json parse error: {}", e),
+			PropsError::Malformed(ref s) => write!(f, "malformed tileset metadata: {}", s),
+			PropsError::UnknownTile(id) => write!(f, "tile {} is outside of the atlas", id),
+		}
+	}
+}
+
+impl From<io::Error> for PropsError {
+	fn from(e: io::Error) -> PropsError {
+		PropsError::Io(e)
+	}
+}
+
+impl From<json::BuilderError> for PropsError {
+	fn from(e: json::BuilderError) -> PropsError {
+		PropsError::Json(e)
+	}
+}
+
+/// Gameplay properties of one atlas entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileProps {
+	pub solid: bool,
+	/// Solid only when landed on from above.
+	pub one_way: bool,
+	/// Blocks line of sight.
+	pub opaque: bool,
+	pub friction: f32,
+	pub damage_per_second: f32,
+	pub tags: Vec<String>,
+}
+
+impl TileProps {
+	pub fn new() -> TileProps {
+		TileProps {
+			solid: false,
+			one_way: false,
+			opaque: false,
+			friction: 1.0,
+			damage_per_second: 0.0,
+			tags: Vec::new(),
+		}
+	}
+
+	pub fn has_tag(&self, tag: &str) -> bool {
+		self.tags.iter().any(|t| t == tag)
+	}
+}
+
+/// Parses tileset metadata of the form
+/// `{ "tiles": [ { "id": 11, "solid": true, "opaque": true, "friction": 1.0, "damage": 0.0, "one_way": false, "tags": ["wall"] } ] }`.
+/// Every field but `id` is optional, and ids left out get `TileProps::new()`.
+pub fn parse(src: &str, num_entries: u32) -> Result<Vec<TileProps>, PropsError> {
+	let root = try!(Json::from_str(src));
+	let mut props = vec![TileProps::new(); num_entries as usize];
+
+	let tiles = match root.find("tiles").and_then(|v| v.as_array()) {
+		Some(tiles) => tiles,
+		None => { return Err(PropsError::Malformed(String::from("missing 'tiles'"))); },
+	};
+
+	for tile in tiles {
+		let id = match tile.find("id").and_then(|v| v.as_u64()) {
+			Some(id) => id as u32,
+			None => { return Err(PropsError::Malformed(String::from("tile entry without an 'id'"))); },
+		};
+		if id >= num_entries {
+			return Err(PropsError::UnknownTile(id));
+		}
+
+		let entry = &mut props[id as usize];
+		if let Some(solid) = tile.find("solid").and_then(|v| v.as_boolean()) {
+			entry.solid = solid;
+		}
+		if let Some(one_way) = tile.find("one_way").and_then(|v| v.as_boolean()) {
+			entry.one_way = one_way;
+		}
+		if let Some(opaque) = tile.find("opaque").and_then(|v| v.as_boolean()) {
+			entry.opaque = opaque;
+		}
+		if let Some(friction) = tile.find("friction").and_then(|v| v.as_f64()) {
+			entry.friction = friction as f32;
+		}
+		if let Some(damage) = tile.find("damage").and_then(|v| v.as_f64()) {
+			entry.damage_per_second = damage as f32;
+		}
+		if let Some(tags) = tile.find("tags").and_then(|v| v.as_array()) {
+			for tag in tags {
+				match tag.as_string() {
+					Some(tag) => entry.tags.push(String::from(tag)),
+					None => { return Err(PropsError::Malformed(format!("non-string tag on tile {}", id))); },
+				}
+			}
+		}
+	}
+
+	Ok(props)
+}
@@ -0,0 +1,211 @@
+//! Grid traversal raycasts over a map, visiting every cell a ray passes
+//! through in order (Amanatides and Woo's DDA).
+
+use std::f32;
+
+use map::{EdgePolicy, Map};
+
+/// Where a ray stopped.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RayHit {
+	/// The blocking cell.
+	pub cell: (i32, i32),
+	/// World position where the ray entered the cell.
+	pub point: (f32, f32),
+	/// Unit normal of the face that was hit, (0, 0) if the ray started inside the cell.
+	pub normal: (i32, i32),
+	/// Distance travelled along the ray, in tiles.
+	pub distance: f32,
+}
+
+impl<'a> Map<'a> {
+	/// First solid cell along the ray from `origin` in direction `dir`, within
+	/// `max_distance` tiles.
+	pub fn raycast(&self, origin: (f32, f32), dir: (f32, f32), max_distance: f32) -> Option<RayHit> {
+		self.raycast_with(origin, dir, max_distance, |map, x, y| map.is_solid(x, y))
+	}
+
+	/// First cell along the ray for which `blocks` is true. `dir` needn't be
+	/// normalized, and `max_distance` may be infinite to cast until something
+	/// is hit. A zero or non-finite `dir`, a non-finite `origin` or a NaN
+	/// `max_distance` never hits anything.
+	///
+	/// The ray also stops once it has left a bounded map for good, or reaches
+	/// an unloaded cell of a streaming map. Wrapping maps cap the distance at
+	/// twice their area in tiles, so a ray that never hits still ends.
+	pub fn raycast_with<F: Fn(&Map, i32, i32) -> bool>(&self, origin: (f32, f32), dir: (f32, f32), max_distance: f32, blocks: F) -> Option<RayHit> {
+		let length = (dir.0 * dir.0 + dir.1 * dir.1).sqrt();
+		if length == 0.0 || !length.is_finite() || max_distance.is_nan() || !origin.0.is_finite() || !origin.1.is_finite() {
+			return None;
+		}
+		let dir = (dir.0 / length, dir.1 / length);
+		let wraps = self.bounded && self.edge == EdgePolicy::Wrap;
+		let max_distance = if wraps { max_distance.min(2.0 * (self.width as f32) * (self.height as f32)) } else { max_distance };
+
+		let mut cell = (origin.0.floor() as i32, origin.1.floor() as i32);
+		if blocks(self, cell.0, cell.1) {
+			return Some(RayHit {
+				cell: cell,
+				point: origin,
+				normal: (0, 0),
+				distance: 0.0,
+			});
+		}
+
+		let step = (if dir.0 < 0.0 { -1 } else { 1 }, if dir.1 < 0.0 { -1 } else { 1 });
+		// Distance along the ray to cross one whole cell on each axis.
+		let delta = (if dir.0 == 0.0 { f32::INFINITY } else { 1.0 / dir.0.abs() }, if dir.1 == 0.0 { f32::INFINITY } else { 1.0 / dir.1.abs() });
+		// Distance along the ray to the next cell boundary on each axis.
+		let mut next = (
+			if dir.0 > 0.0 { (cell.0 as f32 + 1.0 - origin.0) * delta.0 } else if dir.0 < 0.0 { (origin.0 - cell.0 as f32) * delta.0 } else { f32::INFINITY },
+			if dir.1 > 0.0 { (cell.1 as f32 + 1.0 - origin.1) * delta.1 } else if dir.1 < 0.0 { (origin.1 - cell.1 as f32) * delta.1 } else { f32::INFINITY },
+		);
+
+		loop {
+			let (distance, normal) = if next.0 < next.1 {
+				cell.0 += step.0;
+				let distance = next.0;
+				next.0 += delta.0;
+				(distance, (-step.0, 0))
+			} else {
+				cell.1 += step.1;
+				let distance = next.1;
+				next.1 += delta.1;
+				(distance, (0, -step.1))
+			};
+
+			if distance > max_distance || (!wraps && self.leaving(cell, dir)) {
+				return None;
+			}
+
+			if blocks(self, cell.0, cell.1) {
+				return Some(RayHit {
+					cell: cell,
+					point: (origin.0 + dir.0 * distance, origin.1 + dir.1 * distance),
+					normal: normal,
+					distance: distance,
+				});
+			}
+		}
+	}
+
+	/// True if a ray in direction `dir` at `cell` can never reach a cell of
+	/// the map again: off a bounded map and heading away, or in an unloaded
+	/// part of a streaming map.
+	fn leaving(&self, cell: (i32, i32), dir: (f32, f32)) -> bool {
+		if !self.bounded {
+			return self.get(cell.0, cell.1).is_none();
+		}
+		(cell.0 < 0 && dir.0 <= 0.0) || (cell.0 >= self.width && dir.0 >= 0.0) ||
+			(cell.1 < 0 && dir.1 <= 0.0) || (cell.1 >= self.height && dir.1 >= 0.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::f32;
+
+	use gen::GridGenerator;
+	use map::{EdgePolicy, Map};
+	use tile::TileAtlas;
+
+	const WALL: u32 = 11;
+
+	fn map<'a>(atlas: &'a TileAtlas) -> Map<'a> {
+		Map::generate(10, 10, &GridGenerator::new(), 0, 10.0, 1.0, atlas)
+	}
+
+	fn close(a: f32, b: f32) -> bool {
+		(a - b).abs() < 1e-4
+	}
+
+	#[test]
+	fn hits_a_wall_along_an_axis() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(5, 2, WALL).unwrap();
+
+		let hit = map.raycast((1.5, 2.5), (1.0, 0.0), 20.0).unwrap();
+		assert_eq!(hit.cell, (5, 2));
+		assert_eq!(hit.normal, (-1, 0));
+		assert!(close(hit.distance, 3.5));
+		assert!(close(hit.point.0, 5.0) && close(hit.point.1, 2.5));
+	}
+
+	#[test]
+	fn diagonal_hit_reports_the_face_it_crossed() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		for x in 0..10 {
+			map.set(x, 0, WALL).unwrap();
+		}
+
+		let hit = map.raycast((3.5, 4.5), (1.0, -2.0), 20.0).unwrap();
+		assert_eq!(hit.cell, (5, 0));
+		assert_eq!(hit.normal, (0, 1));
+		assert!(close(hit.point.0, 5.25) && close(hit.point.1, 1.0));
+		assert!(close(hit.distance, 3.5 * 5.0f32.sqrt() / 2.0));
+	}
+
+	#[test]
+	fn starting_inside_a_wall_hits_at_once() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(2, 2, WALL).unwrap();
+
+		let hit = map.raycast((2.25, 2.75), (0.0, 1.0), 5.0).unwrap();
+		assert_eq!(hit.cell, (2, 2));
+		assert_eq!(hit.normal, (0, 0));
+		assert_eq!(hit.distance, 0.0);
+		assert_eq!(hit.point, (2.25, 2.75));
+	}
+
+	#[test]
+	fn misses_beyond_max_distance() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(5, 2, WALL).unwrap();
+
+		assert!(map.raycast((1.5, 2.5), (1.0, 0.0), 3.0).is_none());
+		assert!(map.raycast((1.5, 2.5), (1.0, 0.0), 3.5).is_some());
+	}
+
+	#[test]
+	fn refuses_degenerate_rays() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(5, 2, WALL).unwrap();
+
+		assert!(map.raycast((1.5, 2.5), (0.0, 0.0), 10.0).is_none());
+		assert!(map.raycast((1.5, 2.5), (f32::NAN, 0.0), 10.0).is_none());
+		assert!(map.raycast((1.5, 2.5), (1.0, 0.0), f32::NAN).is_none());
+		assert!(map.raycast((f32::NAN, 2.5), (1.0, 0.0), 10.0).is_none());
+	}
+
+	#[test]
+	fn casts_without_a_limit() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set(5, 2, WALL).unwrap();
+
+		let hit = map.raycast((1.5, 2.5), (1.0, 0.0), f32::INFINITY).unwrap();
+		assert_eq!(hit.cell, (5, 2));
+	}
+
+	#[test]
+	fn stops_after_leaving_an_open_map() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = map(&atlas);
+		map.set_edge(EdgePolicy::Void, 0);
+
+		assert!(map.raycast((1.5, 2.5), (-1.0, 0.3), f32::MAX).is_none());
+		assert!(map.raycast((1.5, 2.5), (0.0, 1.0), f32::INFINITY).is_none());
+		// Starting off the map and heading back in still finds walls.
+		map.set(0, 2, WALL).unwrap();
+		assert_eq!(map.raycast((-40.5, 2.5), (1.0, 0.0), f32::MAX).map(|hit| hit.cell), Some((0, 2)));
+
+		map.set_edge(EdgePolicy::Wrap, 0);
+		map.set(0, 2, 0).unwrap();
+		assert!(map.raycast((1.5, 2.5), (1.0, 0.1), f32::INFINITY).is_none());
+	}
+}
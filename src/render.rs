@@ -0,0 +1,50 @@
+//! Drawing without knowing what draws.
+//!
+//! Everything on screen is a unit quad spanning -1 to 1 on both axes, placed
+//! by a column-major matrix into normalized device coordinates, the same as
+//! the vertex shader sees it. Texels whose alpha is zero are never drawn, and
+//! everything else is blended over what is already there.
+
+use image;
+
+use tile::TileAtlas;
+
+pub type Matrix = [[f32; 4]; 4];
+/// Red, green, blue and alpha, each from 0 to 1.
+pub type Color = (f32, f32, f32, f32);
+
+pub const IDENTITY: Matrix = [
+	[1.0, 0.0, 0.0, 0.0],
+	[0.0, 1.0, 0.0, 0.0],
+	[0.0, 0.0, 1.0, 0.0],
+	[0.0, 0.0, 0.0, 1.0],
+];
+
+/// A whole image handed to a renderer by `load_texture`, unique within that renderer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TextureId(pub usize);
+
+pub trait Renderer {
+	/// Takes ownership of an image, top row first, for drawing with `draw_texture`.
+	fn load_texture(&mut self, img: image::RgbaImage) -> TextureId;
+
+	/// Loads an atlas' sheet so `draw_sprite` can draw its entries.
+	fn load_atlas(&mut self, atlas: &mut TileAtlas) {
+		atlas.texture = Some(self.load_texture(atlas.image.clone()));
+	}
+
+	fn clear(&mut self, color: Color);
+
+	/// Draws one atlas entry, as is; callers resolve animations first. Does
+	/// nothing for atlases not loaded with `load_atlas`.
+	fn draw_sprite(&mut self, atlas: &TileAtlas, entry: u32, matrix: Matrix, opacity: f32);
+
+	fn draw_texture(&mut self, texture: TextureId, matrix: Matrix, opacity: f32);
+
+	/// Fills the quad with a flat color.
+	fn draw_quad(&mut self, matrix: Matrix, color: Color);
+
+	/// Draws a line of text starting at the matrix's origin, one unit tall
+	/// before the matrix scales it.
+	fn draw_text(&mut self, text: &str, matrix: Matrix, color: Color);
+}
@@ -0,0 +1,340 @@
+//! Recording of per-tick input, and playing it back.
+//!
+//! A replay file is the magic "TPRP", a u16 version, the f32 tick rate it
+//! was recorded at and a u32 tick count, then per tick:
+//!
+//! ```text
+//! keys      u32   pressed actions, packed by `Inputs::key_bits`
+//! mouse     u8    bit 0 left held, bit 1 right held, bit 2 cursor known
+//! cursor    i32 x, i32 y, window pixels, 0 when unknown
+//! clicked   u8    0 none, 1 left, 2 right, 3 middle, 4 other followed by a u8
+//! checksum  u64   `Game::checksum` after the tick
+//! ```
+//!
+//! Numbers are little endian, as in `save`.
+//!
+//! Only the game is checked for drift. `Game::checksum` covers the player's
+//! position and velocity, the controller's carried-over state (coyote time,
+//! jump buffer), the score, the entities, every loaded tile of every layer
+//! and the explored fog; it knows nothing of the editor. While recording or replaying, the editor starts from
+//! `Editor::scratch` and never saves, so it plays back the same either way.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use keyboard::{Inputs, KeyState, MouseButton};
+use map::Map;
+use save::{self, SaveError};
+use tile::EMPTY_TILE;
+
+pub const REPLAY_MAGIC: &'static [u8; 4] = b"TPRP";
+pub const REPLAY_VERSION: u16 = 1;
+
+/// FNV-1a, for checksums that stay the same across runs and platforms.
+pub struct Checksum {
+	hash: u64,
+}
+
+impl Checksum {
+	pub fn new() -> Checksum {
+		Checksum {
+			hash: 0xcbf29ce484222325,
+		}
+	}
+
+	pub fn write_u8(&mut self, n: u8) {
+		self.hash ^= n as u64;
+		self.hash = self.hash.wrapping_mul(0x100000001b3);
+	}
+
+	pub fn write_u32(&mut self, n: u32) {
+		for i in 0..4 {
+			self.write_u8((n >> (i * 8)) as u8);
+		}
+	}
+
+	pub fn write_f32(&mut self, n: f32) {
+		self.write_u32(n.to_bits());
+	}
+
+	pub fn finish(&self) -> u64 {
+		self.hash
+	}
+}
+
+/// Everything one tick of a scene was handed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tick {
+	pub keys: u32,
+	pub mouse_left: bool,
+	pub mouse_right: bool,
+	pub coords: Option<(i32, i32)>,
+	pub clicked: Option<MouseButton>,
+	pub checksum: u64,
+}
+
+impl Tick {
+	pub fn new(inputs: &Inputs, coords: Option<(i32, i32)>, clicked: Option<MouseButton>) -> Tick {
+		Tick {
+			keys: inputs.key_bits(),
+			mouse_left: inputs.mouse_left == KeyState::Pressed,
+			mouse_right: inputs.mouse_right == KeyState::Pressed,
+			coords: coords,
+			clicked: clicked,
+			checksum: 0,
+		}
+	}
+
+	/// Sets `inputs` to what was held during this tick.
+	pub fn apply(&self, inputs: &mut Inputs) {
+		inputs.set_key_bits(self.keys);
+		inputs.mouse_left = if self.mouse_left { KeyState::Pressed } else { KeyState::Released };
+		inputs.mouse_right = if self.mouse_right { KeyState::Pressed } else { KeyState::Released };
+	}
+
+	fn write<W: Write>(&self, w: &mut W) -> Result<(), SaveError> {
+		try!(save::write_u32(w, self.keys));
+		let mouse = (self.mouse_left as u8) | ((self.mouse_right as u8) << 1) | ((self.coords.is_some() as u8) << 2);
+		try!(w.write_all(&[mouse]));
+
+		let (x, y) = self.coords.unwrap_or((0, 0));
+		try!(save::write_u32(w, x as u32));
+		try!(save::write_u32(w, y as u32));
+
+		match self.clicked {
+			None => try!(w.write_all(&[0])),
+			Some(MouseButton::Left) => try!(w.write_all(&[1])),
+			Some(MouseButton::Right) => try!(w.write_all(&[2])),
+			Some(MouseButton::Middle) => try!(w.write_all(&[3])),
+			Some(MouseButton::Other(n)) => try!(w.write_all(&[4, n])),
+		}
+
+		try!(save::write_u32(w, self.checksum as u32));
+		try!(save::write_u32(w, (self.checksum >> 32) as u32));
+		Ok(())
+	}
+
+	fn read<R: Read>(r: &mut R) -> Result<Tick, SaveError> {
+		let keys = try!(save::read_u32(r));
+		let mut mouse = [0; 1];
+		try!(r.read_exact(&mut mouse));
+		let x = try!(save::read_u32(r)) as i32;
+		let y = try!(save::read_u32(r)) as i32;
+
+		let mut clicked = [0; 1];
+		try!(r.read_exact(&mut clicked));
+		let clicked = match clicked[0] {
+			0 => None,
+			1 => Some(MouseButton::Left),
+			2 => Some(MouseButton::Right),
+			3 => Some(MouseButton::Middle),
+			4 => {
+				let mut n = [0; 1];
+				try!(r.read_exact(&mut n));
+				Some(MouseButton::Other(n[0]))
+			},
+			n => { return Err(SaveError::Malformed(format!("unknown mouse button {}", n))); },
+		};
+
+		let low = try!(save::read_u32(r)) as u64;
+		let high = try!(save::read_u32(r)) as u64;
+
+		Ok(Tick {
+			keys: keys,
+			mouse_left: mouse[0] & 1 != 0,
+			mouse_right: mouse[0] & 2 != 0,
+			coords: if mouse[0] & 4 != 0 { Some((x, y)) } else { None },
+			clicked: clicked,
+			checksum: low | (high << 32),
+		})
+	}
+}
+
+/// A run's input, tick by tick, with the game's checksum after each.
+pub struct Replay {
+	pub tick_rate: f32,
+	pub ticks: Vec<Tick>,
+}
+
+impl Replay {
+	pub fn new(tick_rate: f32) -> Replay {
+		Replay {
+			tick_rate: tick_rate,
+			ticks: Vec::new(),
+		}
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveError> {
+		let mut w = BufWriter::new(try!(File::create(path)));
+		try!(self.write(&mut w));
+		try!(w.flush());
+		Ok(())
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, SaveError> {
+		Replay::read(&mut BufReader::new(try!(File::open(path))))
+	}
+
+	pub fn write<W: Write>(&self, mut w: &mut W) -> Result<(), SaveError> {
+		try!(w.write_all(REPLAY_MAGIC));
+		try!(save::write_u16(&mut w, REPLAY_VERSION));
+		try!(save::write_u32(&mut w, self.tick_rate.to_bits()));
+		try!(save::write_u32(&mut w, self.ticks.len() as u32));
+		for tick in self.ticks.iter() {
+			try!(tick.write(&mut w));
+		}
+		Ok(())
+	}
+
+	pub fn read<R: Read>(mut r: &mut R) -> Result<Replay, SaveError> {
+		let mut magic = [0; 4];
+		try!(r.read_exact(&mut magic));
+		if &magic != REPLAY_MAGIC {
+			return Err(SaveError::BadMagic);
+		}
+
+		let version = try!(save::read_u16(&mut r));
+		if version != REPLAY_VERSION {
+			return Err(SaveError::UnsupportedVersion(version));
+		}
+
+		let tick_rate = f32::from_bits(try!(save::read_u32(&mut r)));
+		if !tick_rate.is_finite() || tick_rate <= 0.0 {
+			return Err(SaveError::Malformed(format!("bad tick rate {}", tick_rate)));
+		}
+		let count = try!(save::read_u32(&mut r));
+		let mut ticks = Vec::new();
+		for _ in 0..count {
+			ticks.push(try!(Tick::read(&mut r)));
+		}
+
+		Ok(Replay {
+			tick_rate: tick_rate,
+			ticks: ticks,
+		})
+	}
+}
+
+impl<'a> Map<'a> {
+	/// Feeds every loaded tile of every layer, and the explored cells if the
+	/// map has fog of war, into `sum`, in an order that doesn't depend on how
+	/// the chunks happen to be stored.
+	pub fn checksum(&self, sum: &mut Checksum) {
+		for layer in 0..self.layers.len() {
+			let mut cells = self.loaded_cells(layer);
+			cells.sort();
+			for (x, y) in cells {
+				sum.write_u32(x as u32);
+				sum.write_u32(y as u32);
+				sum.write_u32(self.get_layer(layer, x, y).map_or(EMPTY_TILE, |tile| tile.tex_id));
+			}
+		}
+
+		if let Some(ref fog) = self.fog {
+			let mut explored: Vec<&(i32, i32)> = fog.explored.iter().collect();
+			explored.sort();
+			for &&(x, y) in explored.iter() {
+				sum.write_u32(x as u32);
+				sum.write_u32(y as u32);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+	use gen::GridGenerator;
+	use keyboard::MouseButton;
+	use save::{self, SaveError};
+	use tile::TileAtlas;
+
+	fn tick(keys: u32, coords: Option<(i32, i32)>, clicked: Option<MouseButton>, checksum: u64) -> Tick {
+		Tick {
+			keys: keys,
+			mouse_left: keys & 1 != 0,
+			mouse_right: keys & 2 != 0,
+			coords: coords,
+			clicked: clicked,
+			checksum: checksum,
+		}
+	}
+
+	#[test]
+	fn ticks_round_trip() {
+		let ticks = vec![
+			tick(0, None, None, 0),
+			tick(0b101, Some((-3, 700)), Some(MouseButton::Left), 0xdeadbeef_00c0ffee),
+			tick(0b10, Some((0, 0)), Some(MouseButton::Other(9)), u64::max_value()),
+			tick(1 << 20, None, Some(MouseButton::Middle), 1),
+		];
+		for tick in ticks.iter() {
+			let mut bytes = Vec::new();
+			tick.write(&mut bytes).unwrap();
+			assert_eq!(&Tick::read(&mut Cursor::new(bytes)).unwrap(), tick);
+		}
+	}
+
+	#[test]
+	fn replays_round_trip() {
+		let mut replay = Replay::new(60.0);
+		replay.ticks.push(tick(3, Some((10, 20)), None, 42));
+		replay.ticks.push(tick(0, None, Some(MouseButton::Right), 43));
+
+		let mut bytes = Vec::new();
+		replay.write(&mut bytes).unwrap();
+		let loaded = Replay::read(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(loaded.tick_rate, 60.0);
+		assert_eq!(loaded.ticks, replay.ticks);
+	}
+
+	#[test]
+	fn rejects_bad_tick_rates() {
+		for &rate in [0.0, -30.0, ::std::f32::NAN, ::std::f32::INFINITY].iter() {
+			let mut bytes = Vec::new();
+			bytes.extend_from_slice(REPLAY_MAGIC);
+			save::write_u16(&mut bytes, REPLAY_VERSION).unwrap();
+			save::write_u32(&mut bytes, f32::to_bits(rate)).unwrap();
+			save::write_u32(&mut bytes, 0).unwrap();
+			match Replay::read(&mut Cursor::new(bytes)) {
+				Err(SaveError::Malformed(_)) => { },
+				_ => panic!("tick rate {} should be refused", rate),
+			}
+		}
+	}
+
+	#[test]
+	fn checksum_is_fnv1a() {
+		let mut sum = Checksum::new();
+		assert_eq!(sum.finish(), 0xcbf29ce484222325);
+		for &b in b"a".iter() {
+			sum.write_u8(b);
+		}
+		assert_eq!(sum.finish(), 0xaf63dc4c8601ec8c);
+	}
+
+	#[test]
+	fn map_checksum_follows_tiles_and_fog() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = Map::generate(10, 10, &GridGenerator::new(), 0, 10.0, 1.0, &atlas);
+		let sum = |map: &Map| {
+			let mut sum = Checksum::new();
+			map.checksum(&mut sum);
+			sum.finish()
+		};
+
+		let before = sum(&map);
+		assert_eq!(sum(&map), before);
+
+		map.set(3, 3, 11).unwrap();
+		let edited = sum(&map);
+		assert!(edited != before);
+
+		map.enable_fog();
+		map.reveal((1, 1), 3);
+		assert!(sum(&map) != edited);
+	}
+}
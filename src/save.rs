@@ -0,0 +1,580 @@
+//! Native on-disk map format.
+//!
+//! All numbers are little endian.
+//!
+//! ```text
+//! magic        4 bytes   "TPMP"
+//! version      u16       FORMAT_VERSION
+//! width        i32       tiles
+//! height       i32       tiles
+//! tile_width   u32       pixels per atlas entry
+//! tile_height  u32
+//! atlas        u16 length followed by that many utf-8 bytes, the TileAtlas name
+//! ground       u16       index of the layer `Map::get`/`Map::set` work on
+//! edge         u8        EdgePolicy: 0 clamp, 1 wrap, 2 void
+//! void         u32       atlas id read off the edge under the void policy
+//! layers       u16 count, then per layer:
+//!                name     u16 length followed by utf-8 bytes
+//!                visible  u8, 0 or 1
+//!                opacity  f32
+//!                order    i32
+//!                tiles    width * height u32 atlas ids, row-major with row 0
+//!                         at the bottom, EMPTY_TILE for nothing
+//! entities     u32 count, then per entity: f32 x, f32 y, u32 atlas id
+//! fog          u8, 1 if the map has fog of war, then width * height bits of
+//!              explored cells, row-major from the bottom, packed eight to a
+//!              byte starting at the low bit
+//! style        u8        ControlStyle: 0 platformer, 1 top-down
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use controller::ControlStyle;
+use entity::Entity;
+use fog::Fog;
+use map::{EdgePolicy, Layer, Map};
+use tile::{TileAtlas, EMPTY_TILE};
+use utils::translate;
+
+pub const MAGIC: &'static [u8; 4] = b"TPMP";
+pub const FORMAT_VERSION: u16 = 1;
+/// Most cells a map or stamp read from disk may have, so a corrupt header is
+/// reported instead of exhausting memory.
+pub const MAX_CELLS: usize = 1 << 24;
+
+#[derive(Debug)]
+pub enum SaveError {
+	Io(io::Error),
+	BadMagic,
+	UnsupportedVersion(u16),
+	Malformed(String),
+	AtlasMismatch(String),
+	Unbounded,
+}
+
+impl fmt::Display for SaveError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SaveError::Io(ref e) => write!(f, "io error: {}", e),
+			SaveError::BadMagic => write!(f, "not a tilepaste map"),
+			SaveError::UnsupportedVersion(v) => write!(f, "map format version {} is newer than {}", v, FORMAT_VERSION),
+			SaveError::Malformed(ref s) => write!(f, "malformed map: {}", s),
+			SaveError::AtlasMismatch(ref s) => write!(f, "map was made for atlas '{}'", s),
+			SaveError::Unbounded => write!(f, "streaming maps cannot be saved as a single file"),
+		}
+	}
+}
+
+impl From<io::Error> for SaveError {
+	fn from(e: io::Error) -> SaveError {
+		SaveError::Io(e)
+	}
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct LayerData {
+	pub name: String,
+	pub visible: bool,
+	pub opacity: f32,
+	pub order: i32,
+	pub tiles: Vec<u32>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct EntityData {
+	pub x: f32,
+	pub y: f32,
+	pub tex_id: u32,
+}
+
+/// Everything a `Map` persists, detached from the atlas it is drawn with.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MapData {
+	pub width: i32,
+	pub height: i32,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub atlas: String,
+	pub ground: u16,
+	pub edge: EdgePolicy,
+	pub void: u32,
+	pub layers: Vec<LayerData>,
+	pub entities: Vec<EntityData>,
+	/// Explored cells row-major, if the map has fog of war.
+	pub explored: Option<Vec<bool>>,
+	pub style: ControlStyle,
+}
+
+fn layer_ids(map: &Map, layer: &Layer) -> Vec<u32> {
+	let mut ids = Vec::with_capacity(map.size() as usize);
+	for y in 0..map.height {
+		for x in 0..map.width {
+			ids.push(layer.get(x, y).map_or(EMPTY_TILE, |tile| tile.tex_id));
+		}
+	}
+	ids
+}
+
+impl MapData {
+	/// Snapshot of a bounded map. Streaming maps persist through their `ChunkSource` instead.
+	pub fn from_map(map: &Map) -> MapData {
+		let mut layers = Vec::with_capacity(map.layers.len());
+		for layer in map.layers.iter() {
+			layers.push(LayerData {
+				name: layer.name.clone(),
+				visible: layer.visible,
+				opacity: layer.opacity,
+				order: layer.order,
+				tiles: layer_ids(map, layer),
+			});
+		}
+
+		let mut entities = Vec::with_capacity(map.entities().len());
+		for entity in map.entities().iter() {
+			entities.push(EntityData {
+				x: entity.x,
+				y: entity.y,
+				tex_id: entity.tex_id,
+			});
+		}
+
+		let explored = map.fog.as_ref().map(|fog| {
+			let mut explored = Vec::with_capacity(map.size() as usize);
+			for y in 0..map.height {
+				for x in 0..map.width {
+					explored.push(fog.explored.contains(&(x, y)));
+				}
+			}
+			explored
+		});
+
+		MapData {
+			width: map.width,
+			height: map.height,
+			tile_width: map.atlas.tile_width,
+			tile_height: map.atlas.tile_height,
+			atlas: map.atlas.name.clone(),
+			ground: map.ground as u16,
+			edge: map.edge,
+			void: map.void.tex_id,
+			layers: layers,
+			entities: entities,
+			explored: explored,
+			style: map.style,
+		}
+	}
+
+	pub fn build<'a>(&self, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Result<Map<'a>, SaveError> {
+		if self.atlas != atlas.name || self.tile_width != atlas.tile_width || self.tile_height != atlas.tile_height {
+			return Err(SaveError::AtlasMismatch(self.atlas.clone()));
+		}
+
+		if self.ground as usize >= self.layers.len() {
+			return Err(SaveError::Malformed(format!("ground layer {} does not exist", self.ground)));
+		}
+
+		if self.void != EMPTY_TILE && self.void >= atlas.num_entries {
+			return Err(SaveError::Malformed(format!("void tile {} is outside of the atlas", self.void)));
+		}
+
+		let mut layers = Vec::with_capacity(self.layers.len());
+		for data in self.layers.iter() {
+			for id in data.tiles.iter() {
+				if *id != EMPTY_TILE && *id >= atlas.num_entries {
+					return Err(SaveError::Malformed(format!("tile id {} is outside of the atlas", id)));
+				}
+			}
+
+			let mut layer = Layer::new(&data.name, self.width, self.height, data.tiles.clone(), data.order, atlas);
+			layer.visible = data.visible;
+			layer.opacity = data.opacity;
+			layers.push(layer);
+		}
+
+		let mut entity_map = Vec::with_capacity(self.entities.len());
+		for entity in self.entities.iter() {
+			if entity.tex_id >= atlas.num_entries {
+				return Err(SaveError::Malformed(format!("entity tile id {} is outside of the atlas", entity.tex_id)));
+			}
+			entity_map.push(Entity::new(entity.x, entity.y, entity.tex_id, atlas));
+		}
+
+		let mut map = Map::from_layers(self.width, self.height, layers, self.ground as usize, entity_map, view_width, ratio, atlas);
+		map.set_edge(self.edge, self.void);
+		map.style = self.style;
+
+		if let Some(ref explored) = self.explored {
+			if explored.len() != try!(cell_count(self.width, self.height)) {
+				return Err(SaveError::Malformed(format!("{} explored cells for a {}x{} map", explored.len(), self.width, self.height)));
+			}
+
+			let mut fog = Fog::new();
+			for y in 0..self.height {
+				for x in 0..self.width {
+					if explored[translate(x, y, self.width)] {
+						fog.explored.insert((x, y));
+					}
+				}
+			}
+			map.fog = Some(fog);
+		}
+		Ok(map)
+	}
+
+	pub fn write<W: Write>(&self, w: &mut W) -> Result<(), SaveError> {
+		try!(w.write_all(MAGIC));
+		try!(write_u16(w, FORMAT_VERSION));
+		try!(write_u32(w, self.width as u32));
+		try!(write_u32(w, self.height as u32));
+		try!(write_u32(w, self.tile_width));
+		try!(write_u32(w, self.tile_height));
+		try!(write_str(w, &self.atlas));
+		try!(write_u16(w, self.ground));
+		try!(w.write_all(&[edge_to_u8(self.edge)]));
+		try!(write_u32(w, self.void));
+
+		if self.layers.len() > u16::MAX as usize {
+			return Err(SaveError::Malformed(format!("{} layers do not fit in the format", self.layers.len())));
+		}
+		try!(write_u16(w, self.layers.len() as u16));
+		for layer in self.layers.iter() {
+			try!(write_str(w, &layer.name));
+			try!(w.write_all(&[layer.visible as u8]));
+			try!(write_u32(w, layer.opacity.to_bits()));
+			try!(write_u32(w, layer.order as u32));
+			for id in layer.tiles.iter() {
+				try!(write_u32(w, *id));
+			}
+		}
+
+		try!(write_u32(w, self.entities.len() as u32));
+		for entity in self.entities.iter() {
+			try!(write_u32(w, entity.x.to_bits()));
+			try!(write_u32(w, entity.y.to_bits()));
+			try!(write_u32(w, entity.tex_id));
+		}
+
+		match self.explored {
+			Some(ref explored) => {
+				try!(w.write_all(&[1]));
+				let mut bytes = vec![0u8; (explored.len() + 7) / 8];
+				for (i, seen) in explored.iter().enumerate() {
+					if *seen {
+						bytes[i / 8] |= 1 << (i % 8);
+					}
+				}
+				try!(w.write_all(&bytes));
+			},
+			None => { try!(w.write_all(&[0])); },
+		}
+
+		let style = match self.style {
+			ControlStyle::Platformer => 0,
+			ControlStyle::TopDown => 1,
+		};
+		try!(w.write_all(&[style]));
+
+		Ok(())
+	}
+
+	pub fn read<R: Read>(r: &mut R) -> Result<MapData, SaveError> {
+		let mut magic = [0; 4];
+		try!(r.read_exact(&mut magic));
+		if &magic != MAGIC {
+			return Err(SaveError::BadMagic);
+		}
+
+		let version = try!(read_u16(r));
+		if version != FORMAT_VERSION {
+			return Err(SaveError::UnsupportedVersion(version));
+		}
+
+		let width = try!(read_u32(r)) as i32;
+		let height = try!(read_u32(r)) as i32;
+		let size = try!(cell_count(width, height));
+		let tile_width = try!(read_u32(r));
+		let tile_height = try!(read_u32(r));
+		let atlas = try!(read_str(r));
+		let ground = try!(read_u16(r));
+		let edge = match try!(read_u8(r)) {
+			0 => EdgePolicy::Clamp,
+			1 => EdgePolicy::Wrap,
+			2 => EdgePolicy::Void,
+			n => { return Err(SaveError::Malformed(format!("unknown edge policy {}", n))); },
+		};
+		let void = try!(read_u32(r));
+
+		let count = try!(read_u16(r));
+		let mut layers = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let name = try!(read_str(r));
+			let visible = try!(read_u8(r)) != 0;
+			let opacity = f32::from_bits(try!(read_u32(r)));
+			let order = try!(read_u32(r)) as i32;
+			let mut tiles = Vec::with_capacity(size);
+			for _ in 0..size {
+				tiles.push(try!(read_u32(r)));
+			}
+			layers.push(LayerData {
+				name: name,
+				visible: visible,
+				opacity: opacity,
+				order: order,
+				tiles: tiles,
+			});
+		}
+
+		let count = try!(read_u32(r));
+		let mut entities = Vec::new();
+		for _ in 0..count {
+			entities.push(EntityData {
+				x: f32::from_bits(try!(read_u32(r))),
+				y: f32::from_bits(try!(read_u32(r))),
+				tex_id: try!(read_u32(r)),
+			});
+		}
+
+		let explored = match try!(read_u8(r)) {
+			0 => None,
+			1 => {
+				let mut bytes = vec![0u8; (size + 7) / 8];
+				try!(r.read_exact(&mut bytes));
+				Some((0..size).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect())
+			},
+			n => { return Err(SaveError::Malformed(format!("bad fog flag {}", n))); },
+		};
+
+		let style = match try!(read_u8(r)) {
+			0 => ControlStyle::Platformer,
+			1 => ControlStyle::TopDown,
+			n => { return Err(SaveError::Malformed(format!("unknown control style {}", n))); },
+		};
+
+		Ok(MapData {
+			width: width,
+			height: height,
+			tile_width: tile_width,
+			tile_height: tile_height,
+			atlas: atlas,
+			ground: ground,
+			edge: edge,
+			void: void,
+			layers: layers,
+			entities: entities,
+			explored: explored,
+			style: style,
+		})
+	}
+}
+
+/// Number of cells in a `width` by `height` grid, refusing negative sizes
+/// and anything over `MAX_CELLS`.
+pub fn cell_count(width: i32, height: i32) -> Result<usize, SaveError> {
+	if width < 0 || height < 0 {
+		return Err(SaveError::Malformed(format!("bad dimensions {}x{}", width, height)));
+	}
+	match (width as usize).checked_mul(height as usize) {
+		Some(size) if size <= MAX_CELLS => Ok(size),
+		_ => Err(SaveError::Malformed(format!("dimensions {}x{} are too large", width, height))),
+	}
+}
+
+fn edge_to_u8(edge: EdgePolicy) -> u8 {
+	match edge {
+		EdgePolicy::Clamp => 0,
+		EdgePolicy::Wrap => 1,
+		EdgePolicy::Void => 2,
+	}
+}
+
+/// Writes a u16 length and the bytes of `s`, refusing strings too long for the prefix.
+pub fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+	if s.len() > u16::MAX as usize {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("string of {} bytes is too long to save", s.len())));
+	}
+	try!(write_u16(w, s.len() as u16));
+	w.write_all(s.as_bytes())
+}
+
+pub fn read_str<R: Read>(r: &mut R) -> Result<String, SaveError> {
+	let len = try!(read_u16(r)) as usize;
+	let mut bytes = vec![0; len];
+	try!(r.read_exact(&mut bytes));
+	String::from_utf8(bytes).map_err(|_| SaveError::Malformed(String::from("string is not utf-8")))
+}
+
+pub fn write_u16<W: Write>(w: &mut W, n: u16) -> io::Result<()> {
+	w.write_all(&[n as u8, (n >> 8) as u8])
+}
+
+pub fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()> {
+	w.write_all(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8])
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+	let mut buf = [0; 1];
+	try!(r.read_exact(&mut buf));
+	Ok(buf[0])
+}
+
+pub fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+	let mut buf = [0; 2];
+	try!(r.read_exact(&mut buf));
+	Ok((buf[0] as u16) | ((buf[1] as u16) << 8))
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+	let mut buf = [0; 4];
+	try!(r.read_exact(&mut buf));
+	Ok((buf[0] as u32) | ((buf[1] as u32) << 8) | ((buf[2] as u32) << 16) | ((buf[3] as u32) << 24))
+}
+
+impl<'a> Map<'a> {
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveError> {
+		if !self.bounded {
+			return Err(SaveError::Unbounded);
+		}
+
+		let file = try!(File::create(path));
+		let mut w = BufWriter::new(file);
+		try!(MapData::from_map(self).write(&mut w));
+		try!(w.flush());
+		Ok(())
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Result<Map<'a>, SaveError> {
+		let file = try!(File::open(path));
+		let data = try!(MapData::read(&mut BufReader::new(file)));
+		data.build(view_width, ratio, atlas)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+	use controller::ControlStyle;
+	use map::EdgePolicy;
+	use tile::{TileAtlas, EMPTY_TILE};
+
+	fn layer(name: &str, order: i32, tiles: Vec<u32>) -> LayerData {
+		LayerData {
+			name: String::from(name),
+			visible: true,
+			opacity: 1.0,
+			order: order,
+			tiles: tiles,
+		}
+	}
+
+	fn sample() -> MapData {
+		let mut decor = layer("decor", 1, vec![EMPTY_TILE, 3, EMPTY_TILE, 4, 5, EMPTY_TILE]);
+		decor.visible = false;
+		decor.opacity = 0.5;
+
+		MapData {
+			width: 3,
+			height: 2,
+			tile_width: 16,
+			tile_height: 16,
+			atlas: String::from("atlas.png"),
+			ground: 1,
+			edge: EdgePolicy::Wrap,
+			void: 7,
+			layers: vec![layer("back", -2, vec![1, 1, 1, 2, 2, 2]), layer("ground", -1, vec![9, 10, 11, 12, 13, EMPTY_TILE]), decor],
+			entities: vec![EntityData { x: 1.5, y: 0.25, tex_id: 14 }, EntityData { x: -3.0, y: 2.0, tex_id: 6 }],
+			explored: Some(vec![true, false, true, true, false, false]),
+			style: ControlStyle::TopDown,
+		}
+	}
+
+	fn round_trip(data: &MapData) -> Result<MapData, SaveError> {
+		let mut bytes = Vec::new();
+		data.write(&mut bytes).unwrap();
+		MapData::read(&mut Cursor::new(bytes))
+	}
+
+	/// The start of a save, up to the atlas name.
+	fn header(version: u16, data: &MapData) -> Vec<u8> {
+		let mut w = Vec::new();
+		w.extend_from_slice(MAGIC);
+		write_u16(&mut w, version).unwrap();
+		write_u32(&mut w, data.width as u32).unwrap();
+		write_u32(&mut w, data.height as u32).unwrap();
+		write_u32(&mut w, data.tile_width).unwrap();
+		write_u32(&mut w, data.tile_height).unwrap();
+		write_str(&mut w, &data.atlas).unwrap();
+		w
+	}
+
+	#[test]
+	fn saved_map_reloads_identically() {
+		let data = sample();
+		assert_eq!(round_trip(&data).unwrap(), data);
+	}
+
+	#[test]
+	fn built_map_snapshots_identically() {
+		let atlas = TileAtlas::new(16, 16);
+		let data = sample();
+		let map = data.build(20.0, 16.0 / 9.0, &atlas).unwrap();
+		assert_eq!(MapData::from_map(&map), data);
+	}
+
+	#[test]
+	fn round_trips_without_fog() {
+		let mut data = sample();
+		data.explored = None;
+		data.edge = EdgePolicy::Clamp;
+		data.style = ControlStyle::Platformer;
+		assert_eq!(round_trip(&data).unwrap(), data);
+	}
+
+	#[test]
+	fn rejects_entities_outside_the_atlas() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut data = sample();
+		data.entities[1].tex_id = atlas.num_entries;
+		match data.build(20.0, 1.0, &atlas) {
+			Err(SaveError::Malformed(_)) => (),
+			_ => panic!("expected a malformed map"),
+		}
+	}
+
+	#[test]
+	fn refuses_over_long_strings() {
+		let mut data = sample();
+		data.layers[0].name = "x".repeat(u16::MAX as usize + 1);
+		match data.write(&mut Vec::new()) {
+			Err(SaveError::Io(_)) => (),
+			other => panic!("expected an io error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn rejects_huge_dimensions() {
+		let mut data = sample();
+		data.width = 0x7fffffff;
+		data.height = 0x7fffffff;
+		let w = header(FORMAT_VERSION, &data);
+
+		match MapData::read(&mut Cursor::new(w)) {
+			Err(SaveError::Malformed(_)) => (),
+			other => panic!("expected a malformed map, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn rejects_newer_versions() {
+		let w = header(FORMAT_VERSION + 1, &sample());
+		match MapData::read(&mut Cursor::new(w)) {
+			Err(SaveError::UnsupportedVersion(v)) => assert_eq!(v, FORMAT_VERSION + 1),
+			other => panic!("expected an unsupported version, got {:?}", other),
+		}
+	}
+}
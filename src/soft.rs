@@ -0,0 +1,309 @@
+//! Drawing on the CPU into an image, for machines without a GPU.
+//!
+//! Follows the shaders in `gl`: textures are sampled nearest-neighbour,
+//! texels with zero alpha are discarded before the opacity is applied, and
+//! everything else is alpha blended over what is already there.
+//!
+//! ```text
+//! let mut renderer = SoftRenderer::new(1280, 720, "assets/ubuntu.ttf");
+//! renderer.clear((0.0, 0.0, 1.0, 1.0));
+//! harness.game.draw(&mut renderer, 1.0);
+//! renderer.save("frame.png").unwrap();
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use image;
+use rusttype::{point, Font, Scale};
+
+use render::{Color, Matrix, Renderer, TextureId};
+use tile::TileAtlas;
+
+fn to_u8(c: f32) -> u8 {
+	(c.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+/// Blends `color` over one pixel the way `glium::Blend::alpha_blending` does,
+/// alpha channel included.
+fn blend(target: &mut image::RgbaImage, x: u32, y: u32, color: Color) {
+	let src = [color.0, color.1, color.2, color.3];
+	let dst = target.get_pixel_mut(x, y);
+	for i in 0..4 {
+		let old = dst.data[i] as f32 / 255.0;
+		dst.data[i] = to_u8(src[i] * color.3 + old * (1.0 - color.3));
+	}
+}
+
+/// The texel at (u, v), with (0, 0) at the bottom-left of the image as the
+/// GPU sees it, or None if it is fully transparent.
+fn sample(img: &image::RgbaImage, u: f32, v: f32) -> Option<Color> {
+	let (width, height) = img.dimensions();
+	let x = ((u * width as f32).floor() as i64).max(0).min(width as i64 - 1) as u32;
+	let y = ((v * height as f32).floor() as i64).max(0).min(height as i64 - 1) as u32;
+
+	let texel = img.get_pixel(x, height - 1 - y).data;
+	if texel[3] == 0 {
+		return None;
+	}
+	Some((texel[0] as f32 / 255.0, texel[1] as f32 / 255.0, texel[2] as f32 / 255.0, texel[3] as f32 / 255.0))
+}
+
+/// Runs `shade` for every pixel whose center falls in the unit quad placed by
+/// `matrix`, with where in the quad it landed, each from 0 to 1 and (0, 0) at
+/// the bottom-left. The right and top edges are left out so neighbouring
+/// quads never cover the same pixel twice.
+fn fill<F: Fn(f32, f32) -> Option<Color>>(target: &mut image::RgbaImage, matrix: Matrix, shade: F) {
+	let (a, b, tx) = (matrix[0][0], matrix[1][0], matrix[3][0]);
+	let (c, d, ty) = (matrix[0][1], matrix[1][1], matrix[3][1]);
+	let det = a * d - b * c;
+	if det == 0.0 {
+		return;
+	}
+
+	let (width, height) = target.dimensions();
+	let (width, height) = (width as f32, height as f32);
+
+	let mut min = (::std::f32::MAX, ::std::f32::MAX);
+	let mut max = (::std::f32::MIN, ::std::f32::MIN);
+	for &(px, py) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0f32)].iter() {
+		let x = ((a * px + b * py + tx) + 1.0) / 2.0 * width;
+		let y = (1.0 - (c * px + d * py + ty)) / 2.0 * height;
+		min = (min.0.min(x), min.1.min(y));
+		max = (max.0.max(x), max.1.max(y));
+	}
+
+	let x0 = min.0.floor().max(0.0) as u32;
+	let y0 = min.1.floor().max(0.0) as u32;
+	let x1 = max.0.ceil().min(width) as u32;
+	let y1 = max.1.ceil().min(height) as u32;
+
+	for y in y0..y1 {
+		for x in x0..x1 {
+			let nx = (x as f32 + 0.5) / width * 2.0 - 1.0 - tx;
+			let ny = 1.0 - (y as f32 + 0.5) / height * 2.0 - ty;
+			let px = (d * nx - b * ny) / det;
+			let py = (a * ny - c * nx) / det;
+			if px < -1.0 || px >= 1.0 || py < -1.0 || py >= 1.0 {
+				continue;
+			}
+
+			if let Some(color) = shade((px + 1.0) / 2.0, (py + 1.0) / 2.0) {
+				blend(target, x, y, color);
+			}
+		}
+	}
+}
+
+/// Draws on the CPU into `image`, top row first.
+pub struct SoftRenderer {
+	pub image: image::RgbaImage,
+	textures: Vec<image::RgbaImage>,
+	font: Font<'static>,
+}
+
+impl SoftRenderer {
+	pub fn new<P: AsRef<Path>>(width: u32, height: u32, font_path: P) -> SoftRenderer {
+		let mut font_data = Vec::new();
+		File::open(font_path).unwrap().read_to_end(&mut font_data).unwrap();
+		let font = Font::from_bytes(font_data).unwrap();
+
+		SoftRenderer {
+			image: image::RgbaImage::new(width, height),
+			textures: Vec::new(),
+			font: font,
+		}
+	}
+
+	/// Writes what has been drawn so far, in the format the extension names.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		self.image.save(path)
+	}
+}
+
+impl Renderer for SoftRenderer {
+	fn load_texture(&mut self, img: image::RgbaImage) -> TextureId {
+		self.textures.push(img);
+		TextureId(self.textures.len() - 1)
+	}
+
+	fn clear(&mut self, color: Color) {
+		let pixel = image::Rgba([to_u8(color.0), to_u8(color.1), to_u8(color.2), to_u8(color.3)]);
+		for texel in self.image.pixels_mut() {
+			*texel = pixel;
+		}
+	}
+
+	fn draw_sprite(&mut self, atlas: &TileAtlas, entry: u32, matrix: Matrix, opacity: f32) {
+		let img = match atlas.texture {
+			Some(texture) if entry < atlas.num_entries => &self.textures[texture.0],
+			_ => { return; },
+		};
+
+		let (bottom_left, top_right) = atlas.tex_coords(entry);
+		fill(&mut self.image, matrix, |u, v| {
+			let u = bottom_left[0] + (top_right[0] - bottom_left[0]) * u;
+			let v = bottom_left[1] + (top_right[1] - bottom_left[1]) * v;
+			sample(img, u, v).map(|texel| (texel.0, texel.1, texel.2, texel.3 * opacity))
+		});
+	}
+
+	fn draw_texture(&mut self, texture: TextureId, matrix: Matrix, opacity: f32) {
+		let img = &self.textures[texture.0];
+		fill(&mut self.image, matrix, |u, v| {
+			sample(img, u, v).map(|texel| (texel.0, texel.1, texel.2, texel.3 * opacity))
+		});
+	}
+
+	fn draw_quad(&mut self, matrix: Matrix, color: Color) {
+		fill(&mut self.image, matrix, |_, _| Some(color));
+	}
+
+	/// Only the scale and translation of `matrix` are used.
+	fn draw_text(&mut self, text: &str, matrix: Matrix, color: Color) {
+		let (width, height) = self.image.dimensions();
+		let scale = Scale {
+			x: matrix[0][0] * width as f32 / 2.0,
+			y: matrix[1][1] * height as f32 / 2.0,
+		};
+		if scale.x <= 0.0 || scale.y <= 0.0 {
+			return;
+		}
+
+		let baseline = point((matrix[3][0] + 1.0) / 2.0 * width as f32, (1.0 - matrix[3][1]) / 2.0 * height as f32);
+		let target = &mut self.image;
+		for glyph in self.font.layout(text, scale, baseline) {
+			let bounds = match glyph.pixel_bounding_box() {
+				Some(bounds) => bounds,
+				None => { continue; },
+			};
+
+			glyph.draw(|gx, gy, coverage| {
+				let x = bounds.min.x + gx as i32;
+				let y = bounds.min.y + gy as i32;
+				if coverage > 0.0 && x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+					blend(target, x as u32, y as u32, (color.0, color.1, color.2, color.3 * coverage));
+				}
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use image;
+
+	use super::*;
+	use render::{Renderer, IDENTITY};
+	use tile::TileAtlas;
+
+	fn renderer(width: u32, height: u32) -> SoftRenderer {
+		SoftRenderer::new(width, height, "assets/ubuntu.ttf")
+	}
+
+	fn pixel(renderer: &SoftRenderer, x: u32, y: u32) -> [u8; 4] {
+		renderer.image.get_pixel(x, y).data
+	}
+
+	/// A 2x2 texture: opaque red and green on top, transparent and opaque
+	/// blue below.
+	fn checker() -> image::RgbaImage {
+		let mut img = image::RgbaImage::new(2, 2);
+		img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+		img.put_pixel(1, 0, image::Rgba([0, 255, 0, 255]));
+		img.put_pixel(0, 1, image::Rgba([0, 0, 0, 0]));
+		img.put_pixel(1, 1, image::Rgba([0, 0, 255, 255]));
+		img
+	}
+
+	#[test]
+	fn quad_blends_over_the_background() {
+		let mut renderer = renderer(4, 4);
+		renderer.clear((0.0, 0.0, 0.0, 1.0));
+		// The left half of the screen.
+		let matrix = [
+			[0.5, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[-0.5, 0.0, 0.0, 1.0],
+		];
+		renderer.draw_quad(matrix, (1.0, 0.0, 0.0, 0.5));
+
+		for y in 0..4 {
+			assert_eq!(pixel(&renderer, 0, y), [128, 0, 0, 191]);
+			assert_eq!(pixel(&renderer, 1, y), [128, 0, 0, 191]);
+			assert_eq!(pixel(&renderer, 2, y), [0, 0, 0, 255]);
+			assert_eq!(pixel(&renderer, 3, y), [0, 0, 0, 255]);
+		}
+	}
+
+	#[test]
+	fn texture_is_sampled_nearest_and_transparent_texels_discarded() {
+		let mut renderer = renderer(4, 4);
+		renderer.clear((1.0, 1.0, 1.0, 1.0));
+		let texture = renderer.load_texture(checker());
+		renderer.draw_texture(texture, IDENTITY, 1.0);
+
+		// Each texel covers a 2x2 block, the right way up.
+		for &(x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+			assert_eq!(pixel(&renderer, x, y), [255, 0, 0, 255]);
+			assert_eq!(pixel(&renderer, x + 2, y), [0, 255, 0, 255]);
+			assert_eq!(pixel(&renderer, x, y + 2), [255, 255, 255, 255]);
+			assert_eq!(pixel(&renderer, x + 2, y + 2), [0, 0, 255, 255]);
+		}
+	}
+
+	#[test]
+	fn opacity_applies_after_the_discard() {
+		let mut renderer = renderer(2, 2);
+		renderer.clear((1.0, 1.0, 1.0, 1.0));
+		let texture = renderer.load_texture(checker());
+		renderer.draw_texture(texture, IDENTITY, 0.5);
+
+		assert_eq!(pixel(&renderer, 0, 0), [255, 128, 128, 191]);
+		assert_eq!(pixel(&renderer, 0, 1), [255, 255, 255, 255]);
+	}
+
+	#[test]
+	fn sprite_matches_its_atlas_entry() {
+		let mut atlas = TileAtlas::new(16, 16);
+		let mut renderer = renderer(atlas.tile_width, atlas.tile_height);
+		renderer.clear((0.0, 0.0, 0.0, 0.0));
+		renderer.draw_sprite(&atlas, 14, IDENTITY, 1.0);
+		assert!(renderer.image.pixels().all(|p| p.data == [0, 0, 0, 0]));
+
+		renderer.load_texture(checker());
+		renderer.load_atlas(&mut atlas);
+		renderer.draw_sprite(&atlas, 14, IDENTITY, 1.0);
+
+		let (bottom_left, top_right) = atlas.tex_coords(14);
+		let left = (bottom_left[0] * atlas.img_width as f32).round() as u32;
+		let top = ((1.0 - top_right[1]) * atlas.img_height as f32).round() as u32;
+		for y in 0..atlas.tile_height {
+			for x in 0..atlas.tile_width {
+				let texel = atlas.image.get_pixel(left + x, top + y).data;
+				let expected = if texel[3] == 0 { [0, 0, 0, 0] } else { texel };
+				assert_eq!(pixel(&renderer, x, y), expected);
+			}
+		}
+	}
+
+	#[test]
+	fn draws_text() {
+		let mut renderer = renderer(64, 32);
+		renderer.clear((0.0, 0.0, 0.0, 1.0));
+		let matrix = [
+			[0.5, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[-1.0, -0.5, 0.0, 1.0],
+		];
+		renderer.draw_text("TP", matrix, (1.0, 1.0, 0.0, 1.0));
+
+		let lit = renderer.image.pixels().filter(|p| p.data[0] > 0).count();
+		assert!(lit > 0);
+		assert!(renderer.image.pixels().all(|p| p.data[2] == 0));
+	}
+}
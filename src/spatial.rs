@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use entity::Entity;
+
+/// Entity indices bucketed by the tile cell they stand in, so region and
+/// radius queries only look at nearby entities.
+pub struct SpatialHash {
+	buckets: HashMap<(i32, i32), Vec<usize>>,
+}
+
+/// The cell holding the point (x, y).
+pub fn cell(x: f32, y: f32) -> (i32, i32) {
+	(x.floor() as i32, y.floor() as i32)
+}
+
+impl SpatialHash {
+	pub fn new() -> SpatialHash {
+		SpatialHash {
+			buckets: HashMap::new(),
+		}
+	}
+
+	pub fn insert(&mut self, index: usize, x: f32, y: f32) {
+		self.buckets.entry(cell(x, y)).or_insert_with(Vec::new).push(index);
+	}
+
+	pub fn remove(&mut self, index: usize, x: f32, y: f32) {
+		let key = cell(x, y);
+		let empty = match self.buckets.get_mut(&key) {
+			Some(bucket) => {
+				bucket.retain(|&i| i != index);
+				bucket.is_empty()
+			},
+			None => false,
+		};
+		if empty {
+			self.buckets.remove(&key);
+		}
+	}
+
+	/// Renames entity `from`, standing at (x, y), to `to`.
+	pub fn renumber(&mut self, from: usize, to: usize, x: f32, y: f32) {
+		if let Some(bucket) = self.buckets.get_mut(&cell(x, y)) {
+			if let Some(slot) = bucket.iter_mut().find(|i| **i == from) {
+				*slot = to;
+			}
+		}
+	}
+
+	/// Re-buckets every entity from scratch.
+	pub fn rebuild(&mut self, entities: &[Entity]) {
+		self.buckets.clear();
+		for (index, entity) in entities.iter().enumerate() {
+			self.insert(index, entity.x, entity.y);
+		}
+	}
+
+	/// Indices of entities whose cell lies in the inclusive cell range.
+	pub fn query_cells(&self, min: (i32, i32), max: (i32, i32)) -> Vec<usize> {
+		let mut found = Vec::new();
+		let columns = (max.0 as i64) - (min.0 as i64) + 1;
+		let rows = (max.1 as i64) - (min.1 as i64) + 1;
+		if columns <= 0 || rows <= 0 {
+			return found;
+		}
+		let area = columns.saturating_mul(rows);
+
+		// Walking every bucket beats walking an area with far more cells than entities.
+		if area > self.buckets.len() as i64 {
+			for (key, bucket) in self.buckets.iter() {
+				if key.0 >= min.0 && key.1 >= min.1 && key.0 <= max.0 && key.1 <= max.1 {
+					found.extend(bucket.iter().cloned());
+				}
+			}
+		} else {
+			for y in (min.1 as i64)..(max.1 as i64 + 1) {
+				for x in (min.0 as i64)..(max.0 as i64 + 1) {
+					if let Some(bucket) = self.buckets.get(&(x as i32, y as i32)) {
+						found.extend(bucket.iter().cloned());
+					}
+				}
+			}
+		}
+
+		found.sort();
+		found
+	}
+}
@@ -0,0 +1,437 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use history::{Edit, History};
+use map::{Map, MapError};
+use save::{self, EntityData, SaveError};
+use tile::EMPTY_TILE;
+use utils::{translate, Rectangle};
+
+pub const LIBRARY_MAGIC: &'static [u8; 4] = b"TPST";
+pub const LIBRARY_VERSION: u16 = 1;
+
+/// Entities are drawn one tile square from their position at the bottom-left corner.
+const ENTITY_SIZE: f32 = 1.0;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct StampLayer {
+	pub name: String,
+	/// Row-major ids with row 0 at the bottom, EMPTY_TILE for transparent cells.
+	pub tiles: Vec<u32>,
+}
+
+/// A captured region of a map that can be pasted elsewhere, rotated or mirrored.
+/// Entity positions are relative to the stamp's bottom-left corner.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Stamp {
+	pub name: String,
+	pub width: i32,
+	pub height: i32,
+	pub layers: Vec<StampLayer>,
+	pub entities: Vec<EntityData>,
+}
+
+impl Stamp {
+	/// Copies every layer and the entities standing inside `area`. Areas too
+	/// large to save are refused.
+	pub fn capture(map: &Map, name: &str, area: &Rectangle<i32>) -> Result<Stamp, SaveError> {
+		let size = try!(save::cell_count(area.width, area.height));
+		let mut layers = Vec::with_capacity(map.layers.len());
+		for (index, layer) in map.layers.iter().enumerate() {
+			let mut tiles = Vec::with_capacity(size);
+			for y in 0..area.height {
+				for x in 0..area.width {
+					let id = map.get_layer(index, area.x + x, area.y + y).map_or(EMPTY_TILE, |tile| tile.tex_id);
+					tiles.push(id);
+				}
+			}
+			layers.push(StampLayer {
+				name: layer.name.clone(),
+				tiles: tiles,
+			});
+		}
+
+		let mut entities = Vec::new();
+		let cells = Rectangle::new(area.x as f32, area.y as f32, area.width as f32, area.height as f32);
+		for index in map.entities_in(&cells) {
+			let entity = &map.entities()[index];
+			entities.push(EntityData {
+				x: entity.x - (area.x as f32),
+				y: entity.y - (area.y as f32),
+				tex_id: entity.tex_id,
+			});
+		}
+
+		Ok(Stamp {
+			name: String::from(name),
+			width: area.width,
+			height: area.height,
+			layers: layers,
+			entities: entities,
+		})
+	}
+
+	/// Rebuilds the stamp at the new width and height, moving each cell by
+	/// `cell` and each entity position by `position`. Cells and entities
+	/// differ because an entity's position is its bottom-left corner, which
+	/// moves by the entity's size rather than a cell's.
+	fn remap<F, G>(&self, width: i32, height: i32, cell: F, position: G) -> Stamp
+		where F: Fn(i32, i32) -> (i32, i32), G: Fn(f32, f32) -> (f32, f32) {
+		let mut layers = Vec::with_capacity(self.layers.len());
+		for layer in self.layers.iter() {
+			let mut tiles = vec![EMPTY_TILE; layer.tiles.len()];
+			for y in 0..self.height {
+				for x in 0..self.width {
+					let (nx, ny) = cell(x, y);
+					tiles[translate(nx, ny, width)] = layer.tiles[translate(x, y, self.width)];
+				}
+			}
+			layers.push(StampLayer {
+				name: layer.name.clone(),
+				tiles: tiles,
+			});
+		}
+
+		let mut entities = Vec::with_capacity(self.entities.len());
+		for entity in self.entities.iter() {
+			let (x, y) = position(entity.x, entity.y);
+			entities.push(EntityData {
+				x: x,
+				y: y,
+				tex_id: entity.tex_id,
+			});
+		}
+
+		Stamp {
+			name: self.name.clone(),
+			width: width,
+			height: height,
+			layers: layers,
+			entities: entities,
+		}
+	}
+
+	/// Rotated a quarter turn clockwise.
+	pub fn rotated(&self) -> Stamp {
+		let w = self.width;
+		let fw = w as f32;
+		self.remap(self.height, self.width, |x, y| (y, w - 1 - x), |x, y| (y, fw - x - ENTITY_SIZE))
+	}
+
+	/// Rotated clockwise by `quarter_turns` quarter turns.
+	pub fn rotated_by(&self, quarter_turns: u32) -> Stamp {
+		let mut stamp = self.clone();
+		for _ in 0..(quarter_turns % 4) {
+			stamp = stamp.rotated();
+		}
+		stamp
+	}
+
+	/// Mirrored left to right.
+	pub fn mirrored_x(&self) -> Stamp {
+		let w = self.width;
+		let fw = w as f32;
+		self.remap(self.width, self.height, |x, y| (w - 1 - x, y), |x, y| (fw - x - ENTITY_SIZE, y))
+	}
+
+	/// Mirrored top to bottom.
+	pub fn mirrored_y(&self) -> Stamp {
+		let h = self.height;
+		let fh = h as f32;
+		self.remap(self.width, self.height, |x, y| (x, h - 1 - y), |x, y| (x, fh - y - ENTITY_SIZE))
+	}
+
+	/// Pastes with the bottom-left corner at (x, y) as one undoable step.
+	/// Transparent cells, cells the map's edge policy refuses, entities standing
+	/// in such cells and layers the map lacks are skipped. On a wrapping map,
+	/// entities wrap along with the cells.
+	pub fn paste(&self, map: &mut Map, history: &mut History, x: i32, y: i32) -> Result<(), MapError> {
+		history.begin_group();
+		let result = self.paste_grouped(map, history, x, y);
+		history.end_group();
+		result
+	}
+
+	fn paste_grouped(&self, map: &mut Map, history: &mut History, x: i32, y: i32) -> Result<(), MapError> {
+
+		for stamp_layer in self.layers.iter() {
+			let layer = match map.layer_index(&stamp_layer.name) {
+				Some(layer) => layer,
+				None => { continue; },
+			};
+
+			for sy in 0..self.height {
+				for sx in 0..self.width {
+					let id = stamp_layer.tiles[translate(sx, sy, self.width)];
+					if id != EMPTY_TILE {
+						try!(history.set_tile(map, layer, x + sx, y + sy, id));
+					}
+				}
+			}
+		}
+
+		for entity in self.entities.iter() {
+			let (ex, ey) = (entity.x + (x as f32), entity.y + (y as f32));
+			let (cell_x, cell_y) = (ex.floor(), ey.floor());
+			let (to_x, to_y) = match map.resolve_write(cell_x as i32, cell_y as i32) {
+				Ok(cell) => cell,
+				Err(_) => { continue; },
+			};
+			let index = map.entities().len();
+			let placed = EntityData {
+				x: (to_x as f32) + (ex - cell_x),
+				y: (to_y as f32) + (ey - cell_y),
+				tex_id: entity.tex_id,
+			};
+			try!(history.apply(map, Edit::AddEntity { index: index, entity: placed }));
+		}
+		Ok(())
+	}
+
+	fn write<W: Write>(&self, w: &mut W) -> Result<(), SaveError> {
+		try!(save::write_str(w, &self.name));
+		try!(save::write_u32(w, self.width as u32));
+		try!(save::write_u32(w, self.height as u32));
+
+		try!(save::write_u16(w, self.layers.len() as u16));
+		for layer in self.layers.iter() {
+			try!(save::write_str(w, &layer.name));
+			for id in layer.tiles.iter() {
+				try!(save::write_u32(w, *id));
+			}
+		}
+
+		try!(save::write_u32(w, self.entities.len() as u32));
+		for entity in self.entities.iter() {
+			try!(save::write_u32(w, entity.x.to_bits()));
+			try!(save::write_u32(w, entity.y.to_bits()));
+			try!(save::write_u32(w, entity.tex_id));
+		}
+		Ok(())
+	}
+
+	fn read<R: Read>(r: &mut R) -> Result<Stamp, SaveError> {
+		let name = try!(save::read_str(r));
+		let width = try!(save::read_u32(r)) as i32;
+		let height = try!(save::read_u32(r)) as i32;
+		let size = try!(save::cell_count(width, height));
+
+		let count = try!(save::read_u16(r));
+		let mut layers = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let layer_name = try!(save::read_str(r));
+			let mut tiles = Vec::with_capacity(size);
+			for _ in 0..size {
+				tiles.push(try!(save::read_u32(r)));
+			}
+			layers.push(StampLayer {
+				name: layer_name,
+				tiles: tiles,
+			});
+		}
+
+		let count = try!(save::read_u32(r));
+		let mut entities = Vec::new();
+		for _ in 0..count {
+			entities.push(EntityData {
+				x: f32::from_bits(try!(save::read_u32(r))),
+				y: f32::from_bits(try!(save::read_u32(r))),
+				tex_id: try!(save::read_u32(r)),
+			});
+		}
+
+		Ok(Stamp {
+			name: name,
+			width: width,
+			height: height,
+			layers: layers,
+			entities: entities,
+		})
+	}
+}
+
+/// Named stamps kept on disk so rooms and props can be reused across maps.
+///
+/// The file is the magic "TPST", a u16 version and a u32 stamp count, then
+/// each stamp as its name, u32 width and height, u16 layer count with each
+/// layer's name and ids, and u32 entity count with f32 x, f32 y, u32 id per
+/// entity, using the same encodings as the map format in `save`.
+pub struct StampLibrary {
+	pub stamps: Vec<Stamp>,
+}
+
+impl StampLibrary {
+	pub fn new() -> StampLibrary {
+		StampLibrary {
+			stamps: Vec::new(),
+		}
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Stamp> {
+		self.stamps.iter().find(|stamp| stamp.name == name)
+	}
+
+	/// Adds a stamp, replacing any with the same name.
+	pub fn insert(&mut self, stamp: Stamp) {
+		self.stamps.retain(|existing| existing.name != stamp.name);
+		self.stamps.push(stamp);
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveError> {
+		let mut w = BufWriter::new(try!(File::create(path)));
+		try!(self.write(&mut w));
+		try!(w.flush());
+		Ok(())
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<StampLibrary, SaveError> {
+		StampLibrary::read(&mut BufReader::new(try!(File::open(path))))
+	}
+
+	pub fn write<W: Write>(&self, mut w: &mut W) -> Result<(), SaveError> {
+		try!(w.write_all(LIBRARY_MAGIC));
+		try!(save::write_u16(&mut w, LIBRARY_VERSION));
+		try!(save::write_u32(&mut w, self.stamps.len() as u32));
+		for stamp in self.stamps.iter() {
+			try!(stamp.write(&mut w));
+		}
+		Ok(())
+	}
+
+	pub fn read<R: Read>(mut r: &mut R) -> Result<StampLibrary, SaveError> {
+		let mut magic = [0; 4];
+		try!(r.read_exact(&mut magic));
+		if &magic != LIBRARY_MAGIC {
+			return Err(SaveError::BadMagic);
+		}
+
+		let version = try!(save::read_u16(&mut r));
+		if version != LIBRARY_VERSION {
+			return Err(SaveError::UnsupportedVersion(version));
+		}
+
+		let count = try!(save::read_u32(&mut r));
+		let mut stamps = Vec::new();
+		for _ in 0..count {
+			stamps.push(try!(Stamp::read(&mut r)));
+		}
+
+		Ok(StampLibrary {
+			stamps: stamps,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs;
+
+	use history::History;
+	use map::{EdgePolicy, Map};
+	use save::{self, EntityData};
+	use stamp::{Stamp, StampLayer, StampLibrary};
+	use tile::{TileAtlas, EMPTY_TILE};
+
+	fn blank(atlas: &TileAtlas) -> Map {
+		Map::from_ids(6, 6, vec![EMPTY_TILE; 36], Vec::new(), 10.0, 1.0, atlas)
+	}
+
+	fn stamp() -> Stamp {
+		Stamp {
+			name: String::from("room"),
+			width: 3,
+			height: 2,
+			layers: vec![StampLayer { name: String::from("ground"), tiles: vec![1, 2, 3, 4, 5, 6] }],
+			entities: vec![EntityData { x: 0.25, y: 0.5, tex_id: 14 }],
+		}
+	}
+
+	#[test]
+	fn mirroring_moves_entities_by_their_size() {
+		let mirrored = stamp().mirrored_x();
+		assert_eq!(mirrored.layers[0].tiles, vec![3, 2, 1, 6, 5, 4]);
+		assert_eq!((mirrored.entities[0].x, mirrored.entities[0].y), (1.75, 0.5));
+
+		let flipped = stamp().mirrored_y();
+		assert_eq!(flipped.layers[0].tiles, vec![4, 5, 6, 1, 2, 3]);
+		assert_eq!((flipped.entities[0].x, flipped.entities[0].y), (0.25, 0.5));
+	}
+
+	#[test]
+	fn rotating_keeps_entities_inside() {
+		let rotated = stamp().rotated();
+		assert_eq!((rotated.width, rotated.height), (2, 3));
+		assert_eq!(rotated.layers[0].tiles, vec![3, 6, 2, 5, 1, 4]);
+		assert_eq!((rotated.entities[0].x, rotated.entities[0].y), (0.5, 1.75));
+		assert_eq!(stamp().rotated_by(4), stamp());
+	}
+
+	#[test]
+	fn oversized_stamps_are_refused() {
+		let mut bytes = Vec::new();
+		save::write_str(&mut bytes, "huge").unwrap();
+		save::write_u32(&mut bytes, 1 << 16).unwrap();
+		save::write_u32(&mut bytes, 1 << 16).unwrap();
+		save::write_u16(&mut bytes, 1).unwrap();
+		assert!(Stamp::read(&mut &bytes[..]).is_err());
+	}
+
+	#[test]
+	fn pastes_as_one_step() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+		let mut holey = stamp();
+		holey.layers[0].tiles[1] = EMPTY_TILE;
+		holey.layers.push(StampLayer { name: String::from("missing"), tiles: vec![7; 6] });
+
+		holey.paste(&mut map, &mut history, 2, 3).unwrap();
+		let row = |map: &Map, y| (1..6).map(|x| map.get(x, y).unwrap().tex_id).collect::<Vec<u32>>();
+		assert_eq!(row(&map, 3), vec![EMPTY_TILE, 1, EMPTY_TILE, 3, EMPTY_TILE]);
+		assert_eq!(row(&map, 4), vec![EMPTY_TILE, 4, 5, 6, EMPTY_TILE]);
+		assert_eq!(map.entities().len(), 1);
+		assert_eq!((map.entities()[0].x, map.entities()[0].y), (2.25, 3.5));
+
+		history.undo(&mut map).unwrap();
+		assert_eq!(row(&map, 3), vec![EMPTY_TILE; 5]);
+		assert_eq!(map.entities().len(), 0);
+	}
+
+	#[test]
+	fn pasting_skips_entities_the_edge_refuses() {
+		let atlas = TileAtlas::new(16, 16);
+		let mut map = blank(&atlas);
+		let mut history = History::new(8);
+
+		map.set_edge(EdgePolicy::Void, EMPTY_TILE);
+		stamp().paste(&mut map, &mut history, -1, 0).unwrap();
+		assert_eq!(map.entities().len(), 0);
+		assert_eq!(map.get(0, 0).unwrap().tex_id, 2);
+
+		map.set_edge(EdgePolicy::Wrap, EMPTY_TILE);
+		stamp().paste(&mut map, &mut history, -1, 2).unwrap();
+		assert_eq!(map.entities().len(), 1);
+		assert_eq!((map.entities()[0].x, map.entities()[0].y), (5.25, 2.5));
+		assert_eq!(map.get(5, 2).unwrap().tex_id, 1);
+	}
+
+	#[test]
+	fn libraries_round_trip_on_disk() {
+		let mut library = StampLibrary::new();
+		library.insert(stamp());
+		library.insert(stamp().rotated());
+		let mut other = stamp().mirrored_y();
+		other.name = String::from("flipped");
+		library.insert(other);
+
+		let path = env::temp_dir().join(format!("tilepaste-stamps-{}.bin", ::std::process::id()));
+		library.save(&path).unwrap();
+		let loaded = StampLibrary::load(&path);
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(loaded.unwrap().stamps, library.stamps);
+		assert_eq!(library.stamps.len(), 2);
+	}
+}
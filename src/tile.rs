@@ -1,11 +1,20 @@
-use std::io::Cursor;
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::fmt;
+use std::path::Path;
 
 use image;
-use glium;
-use glium::Surface;
 
-use vert::Vert;
+use anim;
+use autotile;
+use autotile::AutoTileRule;
+use anim::AnimationSet;
+use props;
+use props::{PropsError, TileProps};
+use render::{Matrix, Renderer, TextureId};
+
+/// Atlas id marking a cell with nothing in it.
+pub const EMPTY_TILE: u32 = ::std::u32::MAX;
 
 pub struct Tile<'a> {
 	pub tex_id: u32,
@@ -26,36 +35,49 @@ impl<'a> Tile<'a> {
 		}
 	}
 
-	pub fn draw(&self, mut target: &mut glium::Frame, program: &glium::Program, matrix: [[f32; 4]; 4]) {
-        let buffer = self.atlas.tex_verts.get(self.tex_id as usize).unwrap();
-        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+	/// Properties of this tile's atlas entry, None for empty cells.
+	pub fn props(&self) -> Option<&'a TileProps> {
+		self.atlas.props(self.tex_id)
+	}
 
-        let tile_uniform = uniform! {
-            matrix: matrix,
-            tex: self.atlas.texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-        };
+	pub fn is_empty(&self) -> bool {
+		self.tex_id == EMPTY_TILE
+	}
 
-        target.draw(buffer, &indices, program, &tile_uniform, &Default::default()).unwrap();
+	pub fn draw(&self, renderer: &mut Renderer, matrix: Matrix, opacity: f32) {
+        if self.is_empty() {
+            return;
+        }
+        renderer.draw_sprite(self.atlas, self.atlas.frame(self.tex_id), matrix, opacity);
     }
 }
 
+fn atlas_image() -> image::RgbaImage {
+    image::load(Cursor::new(&include_bytes!("../assets/atlas.png")[..]), image::PNG).unwrap().to_rgba()
+}
+
 pub struct TileAtlas {
-    pub texture: glium::texture::SrgbTexture2d,
-    pub tex_verts: Vec<glium::VertexBuffer<Vert>>,
+    pub name: String,
+    /// The whole sheet, handed to a renderer by `Renderer::load_atlas`.
+    pub image: image::RgbaImage,
+    /// The sheet's id in the renderer it was loaded into. Sprites from an
+    /// atlas that hasn't been loaded draw nothing.
+    pub texture: Option<TextureId>,
     pub img_width: u32,
     pub img_height: u32,
     pub tile_width: u32,
     pub tile_height: u32,
     pub num_entries: u32,
+    pub props: Vec<TileProps>,
+    pub animations: AnimationSet,
+    /// Autotiling rules from the tileset metadata, applied to maps through `AutoTiler::for_atlas`.
+    pub autotile: Vec<AutoTileRule>,
 }
 
 impl TileAtlas {
-    pub fn new(display: &glium::backend::glutin_backend::GlutinFacade, tile_width: u32, tile_height: u32) -> TileAtlas {
-        let img = image::load(Cursor::new(&include_bytes!("../assets/atlas.png")[..]), image::PNG).unwrap().to_rgba();
+    pub fn new(tile_width: u32, tile_height: u32) -> TileAtlas {
+        let img = atlas_image();
         let dims = img.dimensions();
-        let raw_img = glium::texture::RawImage2d::from_raw_rgba_reversed(img.into_raw(), dims);
-        let texture = glium::texture::SrgbTexture2d::new(display, raw_img).unwrap();
-
         let img_width = dims.0;
         let img_height = dims.1;
 
@@ -63,47 +85,60 @@ impl TileAtlas {
         let y_entries = img_height / tile_height;
         let num_entries = (x_entries * y_entries) as usize;
 
-        let mut tex_verts = Vec::with_capacity(num_entries);
-
-        for i in 0..num_entries {
-			let buffer = glium::VertexBuffer::immutable(display, atlas_verts(i, num_entries).as_slice()).unwrap();
-            tex_verts.push(buffer);
-        }
-
         TileAtlas {
-            texture: texture,
-            tex_verts: tex_verts,
+            name: String::from("atlas.png"),
+            image: img,
+            texture: None,
             img_width: img_width,
             img_height: img_height,
             tile_width: tile_width,
             tile_height: tile_height,
             num_entries: num_entries as u32,
+            props: props::parse(include_str!("../assets/atlas.json"), num_entries as u32).unwrap(),
+            animations: anim::parse(include_str!("../assets/atlas.json"), num_entries as u32).unwrap(),
+            autotile: autotile::parse(include_str!("../assets/atlas.json"), num_entries as u32).unwrap(),
         }
     }
-}
 
-fn atlas_verts(entry: usize, sheet_entries: usize) -> Vec<Vert> {
-    let num_entries = sheet_entries;
-    let col_num = (num_entries as f32).sqrt();
-    let row_num = (num_entries as f32).sqrt();
-
-    let scalar = 1.0 / ((num_entries as f32) / col_num);
-
-    let base_x = entry % (num_entries / (col_num as usize));
-    let base_y = entry / (num_entries / (row_num as usize));
-    let base_x = (base_x as f32) * scalar;
-    let base_y = (base_y as f32) * scalar;
-
-    let bottom_left =  [base_x, base_y];
-    let bottom_right = [base_x + scalar, base_y];
-    let top_left = 	   [base_x, base_y + scalar];
-    let top_right =	   [base_x + scalar, base_y + scalar];
-
-    let vert1 = Vert { position: [-1.0, -1.0], tex_coords: bottom_left };
-    let vert2 = Vert { position: [-1.0,  1.0], tex_coords: top_left };
-    let vert3 = Vert { position: [ 1.0, -1.0], tex_coords: bottom_right };
-    let vert4 = Vert { position: [ 1.0, -1.0], tex_coords: bottom_right };
-    let vert5 = Vert { position: [-1.0,  1.0], tex_coords: top_left };
-    let vert6 = Vert { position: [ 1.0,  1.0], tex_coords: top_right };
-    vec![vert1, vert2, vert3, vert4, vert5, vert6]
+    /// Replaces the tile properties, animations and autotiling rules with those in a tileset metadata file.
+    pub fn load_props<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PropsError> {
+        let mut src = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut src));
+        self.props = try!(props::parse(&src, self.num_entries));
+        self.animations = try!(anim::parse(&src, self.num_entries));
+        self.autotile = try!(autotile::parse(&src, self.num_entries));
+        Ok(())
+    }
+
+    /// Advances every animated tile by `ms` milliseconds.
+    pub fn advance(&self, ms: f32) {
+        self.animations.advance(ms);
+    }
+
+    /// Atlas id to draw for `id`, following its animation if it has one.
+    pub fn frame(&self, id: u32) -> u32 {
+        self.animations.frame(id)
+    }
+
+    pub fn props(&self, id: u32) -> Option<&TileProps> {
+        self.props.get(id as usize)
+    }
+
+    /// Bottom-left and top-right texture coordinates of an entry, with (0, 0)
+    /// at the bottom-left of the sheet.
+    pub fn tex_coords(&self, entry: u32) -> ([f32; 2], [f32; 2]) {
+        let num_entries = self.num_entries as usize;
+        let entry = entry as usize;
+        let col_num = (num_entries as f32).sqrt();
+        let row_num = (num_entries as f32).sqrt();
+
+        let scalar = 1.0 / ((num_entries as f32) / col_num);
+
+        let base_x = entry % (num_entries / (col_num as usize));
+        let base_y = entry / (num_entries / (row_num as usize));
+        let base_x = (base_x as f32) * scalar;
+        let base_y = (base_y as f32) * scalar;
+
+        ([base_x, base_y], [base_x + scalar, base_y + scalar])
+    }
 }
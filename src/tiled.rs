@@ -0,0 +1,559 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::{self, Json};
+use xml::attribute::OwnedAttribute;
+use xml::reader::{self, EventReader, XmlEvent};
+
+use entity::Entity;
+use map::{Layer, Map};
+use save;
+use tile::{TileAtlas, EMPTY_TILE};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+
+#[derive(Debug)]
+pub enum TiledError {
+	Io(io::Error),
+	Xml(reader::Error),
+	Json(json::BuilderError),
+	Malformed(String),
+	UnsupportedEncoding(String),
+	UnknownTileset(String),
+	ExternalTileset(String),
+	UnmappedGid(u32),
+	GidOutOfRange(u32),
+}
+
+impl fmt::Display for TiledError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TiledError::Io(ref e) => write!(f, "io error: {}", e),
+			TiledError::Xml(ref e) => write!(f, "tmx parse error: {}", e),
+			TiledError::Json(ref e) => write!(f, "json parse error: {}", e),
+			TiledError::Malformed(ref s) => write!(f, "malformed map: {}", s),
+			TiledError::UnsupportedEncoding(ref s) => write!(f, "unsupported layer encoding: {}", s),
+			TiledError::UnknownTileset(ref s) => write!(f, "tileset '{}' does not match the tile atlas", s),
+			TiledError::ExternalTileset(ref s) => write!(f, "external tileset '{}' is not supported, embed it in the map", s),
+			TiledError::UnmappedGid(gid) => write!(f, "gid {} does not belong to any tileset", gid),
+			TiledError::GidOutOfRange(gid) => write!(f, "gid {} is outside of the tile atlas", gid),
+		}
+	}
+}
+
+impl From<io::Error> for TiledError {
+	fn from(e: io::Error) -> TiledError {
+		TiledError::Io(e)
+	}
+}
+
+impl From<reader::Error> for TiledError {
+	fn from(e: reader::Error) -> TiledError {
+		TiledError::Xml(e)
+	}
+}
+
+impl From<json::BuilderError> for TiledError {
+	fn from(e: json::BuilderError) -> TiledError {
+		TiledError::Json(e)
+	}
+}
+
+pub struct TiledTileset {
+	pub first_gid: u32,
+	pub name: String,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub tile_count: Option<u32>,
+	pub image: Option<String>,
+	pub source: Option<String>,
+}
+
+impl TiledTileset {
+	/// A tileset stands for the atlas when its image is the atlas image, or,
+	/// lacking an image, when it carries the atlas name without the extension.
+	fn matches(&self, atlas: &TileAtlas) -> bool {
+		if self.tile_width != atlas.tile_width || self.tile_height != atlas.tile_height {
+			return false;
+		}
+		let stem = Path::new(&atlas.name).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+		match self.image {
+			Some(ref image) => Path::new(image).file_name().and_then(|s| s.to_str()) == Some(atlas.name.as_str()),
+			None => self.name == atlas.name || self.name == stem,
+		}
+	}
+}
+
+pub struct TiledLayer {
+	pub name: String,
+	pub gids: Vec<u32>,
+	pub visible: bool,
+	pub opacity: f32,
+	pub above_objects: bool,
+}
+
+pub struct TiledObject {
+	pub gid: u32,
+	pub x: f32,
+	pub y: f32,
+}
+
+/// A map as Tiled describes it, before it is resolved against a `TileAtlas`.
+/// Rows are stored top to bottom, the way Tiled exports them.
+pub struct TiledMap {
+	pub width: i32,
+	pub height: i32,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub tilesets: Vec<TiledTileset>,
+	pub layers: Vec<TiledLayer>,
+	pub objects: Vec<TiledObject>,
+}
+
+impl TiledMap {
+	fn resolve(&self, gid: u32, atlas: &TileAtlas) -> Result<u32, TiledError> {
+		let gid = gid & !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY);
+
+		let mut found: Option<&TiledTileset> = None;
+		for tileset in self.tilesets.iter() {
+			if tileset.first_gid <= gid && found.map_or(true, |best| best.first_gid < tileset.first_gid) {
+				found = Some(tileset);
+			}
+		}
+
+		let tileset = match found {
+			Some(tileset) => tileset,
+			None => { return Err(TiledError::UnmappedGid(gid)); },
+		};
+
+		if let Some(ref source) = tileset.source {
+			return Err(TiledError::ExternalTileset(source.clone()));
+		}
+		if !tileset.matches(atlas) {
+			return Err(TiledError::UnknownTileset(tileset.name.clone()));
+		}
+
+		let id = gid - tileset.first_gid;
+		if let Some(count) = tileset.tile_count {
+			if id >= count {
+				return Err(TiledError::UnmappedGid(gid));
+			}
+		}
+		if id >= atlas.num_entries {
+			return Err(TiledError::GidOutOfRange(gid));
+		}
+
+		Ok(id)
+	}
+
+	/// Resolves every gid against the atlas and builds a `Map`.
+	/// Tile layers keep Tiled's stacking: those listed before the first object
+	/// layer are drawn beneath entities, the rest above them. The ground layer
+	/// is the one named "ground", or else the top-most layer below the objects.
+	/// Objects carrying a gid become entities.
+	pub fn build<'a>(&self, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Result<Map<'a>, TiledError> {
+		if self.width <= 0 || self.height <= 0 {
+			return Err(TiledError::Malformed(format!("bad map size {}x{}", self.width, self.height)));
+		}
+		let size = match (self.width as usize).checked_mul(self.height as usize) {
+			Some(size) if size <= save::MAX_CELLS => size,
+			_ => { return Err(TiledError::Malformed(format!("map size {}x{} is too large", self.width, self.height))); },
+		};
+		let below = self.layers.iter().filter(|layer| !layer.above_objects).count() as i32;
+		let mut layers = Vec::with_capacity(self.layers.len());
+
+		for (i, layer) in self.layers.iter().enumerate() {
+			if layer.gids.len() != size {
+				return Err(TiledError::Malformed(format!("layer '{}' has {} tiles, expected {}", layer.name, layer.gids.len(), size)));
+			}
+
+			let mut ids = vec![EMPTY_TILE; size];
+			for (index, gid) in layer.gids.iter().enumerate() {
+				if *gid == 0 {
+					continue;
+				}
+
+				let x = (index as i32) % self.width;
+				let row = (index as i32) / self.width;
+				let y = self.height - 1 - row;
+				ids[(y * self.width + x) as usize] = try!(self.resolve(*gid, atlas));
+			}
+
+			let order = if layer.above_objects { (i as i32) - below + 1 } else { (i as i32) - below };
+			let mut built = Layer::new(&layer.name, self.width, self.height, ids, order, atlas);
+			built.visible = layer.visible;
+			built.opacity = layer.opacity;
+			layers.push(built);
+		}
+
+		if layers.is_empty() {
+			layers.push(Layer::new("ground", self.width, self.height, vec![EMPTY_TILE; size], -1, atlas));
+		}
+
+		let ground = match layers.iter().position(|layer| layer.name.to_lowercase() == "ground") {
+			Some(index) => index,
+			None => if below > 0 { (below - 1) as usize } else { 0 },
+		};
+
+		let mut entity_map = Vec::new();
+		for object in self.objects.iter() {
+			if object.gid == 0 {
+				continue;
+			}
+
+			let id = try!(self.resolve(object.gid, atlas));
+			let x = object.x / (self.tile_width as f32);
+			let y = (self.height as f32) - (object.y / (self.tile_height as f32));
+			entity_map.push(Entity::new(x, y, id, atlas));
+		}
+
+		Ok(Map::from_layers(self.width, self.height, layers, ground, entity_map, view_width, ratio, atlas))
+	}
+}
+
+/// Loads a `.tmx`, or a Tiled `.tmj`/`.json` export, picking the format from the extension.
+pub fn load<'a, P: AsRef<Path>>(path: P, view_width: f32, ratio: f32, atlas: &'a TileAtlas) -> Result<Map<'a>, TiledError> {
+	let path = path.as_ref();
+	let file = try!(File::open(path));
+	let tiled = match path.extension().and_then(|e| e.to_str()) {
+		Some("json") | Some("tmj") => try!(parse_json(BufReader::new(file))),
+		_ => try!(parse_tmx(BufReader::new(file))),
+	};
+	tiled.build(view_width, ratio, atlas)
+}
+
+fn attr<'b>(attributes: &'b [OwnedAttribute], key: &str) -> Option<&'b str> {
+	for attribute in attributes {
+		if attribute.name.local_name == key {
+			return Some(attribute.value.as_str());
+		}
+	}
+	None
+}
+
+fn attr_num<T: ::std::str::FromStr>(attributes: &[OwnedAttribute], key: &str) -> Result<T, TiledError> {
+	match attr(attributes, key) {
+		Some(value) => value.trim().parse().map_err(|_| TiledError::Malformed(format!("bad value '{}' for '{}'", value, key))),
+		None => Err(TiledError::Malformed(format!("missing attribute '{}'", key))),
+	}
+}
+
+fn decode_base64(data: &str, compression: Option<&str>) -> Result<Vec<u32>, TiledError> {
+	if let Some(compression) = compression {
+		return Err(TiledError::UnsupportedEncoding(format!("base64 with {} compression", compression)));
+	}
+
+	let bytes = try!(data.trim().from_base64().map_err(|_| TiledError::Malformed(String::from("bad base64 tile data"))));
+	if bytes.len() % 4 != 0 {
+		return Err(TiledError::Malformed(String::from("base64 tile data is not a whole number of gids")));
+	}
+
+	let mut gids = Vec::with_capacity(bytes.len() / 4);
+	for chunk in bytes.chunks(4) {
+		gids.push((chunk[0] as u32) | ((chunk[1] as u32) << 8) | ((chunk[2] as u32) << 16) | ((chunk[3] as u32) << 24));
+	}
+	Ok(gids)
+}
+
+fn decode_csv(data: &str) -> Result<Vec<u32>, TiledError> {
+	let mut gids = Vec::new();
+	for value in data.split(',') {
+		let value = value.trim();
+		if value.is_empty() {
+			continue;
+		}
+		gids.push(try!(value.parse().map_err(|_| TiledError::Malformed(format!("bad gid '{}'", value)))));
+	}
+	Ok(gids)
+}
+
+pub fn parse_tmx<R: Read>(reader: R) -> Result<TiledMap, TiledError> {
+	let mut tiled = TiledMap {
+		width: 0,
+		height: 0,
+		tile_width: 0,
+		tile_height: 0,
+		tilesets: Vec::new(),
+		layers: Vec::new(),
+		objects: Vec::new(),
+	};
+
+	let mut layer: Option<TiledLayer> = None;
+	let mut seen_objects = false;
+	let mut in_tileset = false;
+	let mut encoding: Option<String> = None;
+	let mut compression: Option<String> = None;
+	let mut data = String::new();
+
+	for event in EventReader::new(reader) {
+		match try!(event) {
+			XmlEvent::StartElement { name, attributes, .. } => {
+				match name.local_name.as_str() {
+					"map" => {
+						if attr(&attributes, "infinite") == Some("1") {
+							return Err(TiledError::UnsupportedEncoding(String::from("infinite maps")));
+						}
+						tiled.width = try!(attr_num(&attributes, "width"));
+						tiled.height = try!(attr_num(&attributes, "height"));
+						tiled.tile_width = try!(attr_num(&attributes, "tilewidth"));
+						tiled.tile_height = try!(attr_num(&attributes, "tileheight"));
+					},
+					"tileset" => {
+						let source = attr(&attributes, "source").map(String::from);
+						let name = attr(&attributes, "name").map(String::from).or(source.clone()).unwrap_or(String::new());
+						tiled.tilesets.push(TiledTileset {
+							first_gid: try!(attr_num(&attributes, "firstgid")),
+							name: name,
+							tile_width: attr_num(&attributes, "tilewidth").unwrap_or(0),
+							tile_height: attr_num(&attributes, "tileheight").unwrap_or(0),
+							tile_count: attr_num(&attributes, "tilecount").ok(),
+							image: None,
+							source: source,
+						});
+						in_tileset = true;
+					},
+					"image" => {
+						if in_tileset {
+							if let Some(tileset) = tiled.tilesets.last_mut() {
+								tileset.image = attr(&attributes, "source").map(String::from);
+							}
+						}
+					},
+					"layer" => {
+						layer = Some(TiledLayer {
+							name: attr(&attributes, "name").unwrap_or("").to_string(),
+							gids: Vec::new(),
+							visible: attr(&attributes, "visible") != Some("0"),
+							opacity: attr_num(&attributes, "opacity").unwrap_or(1.0),
+							above_objects: seen_objects,
+						});
+					},
+					"objectgroup" => {
+						seen_objects = true;
+					},
+					"data" => {
+						encoding = attr(&attributes, "encoding").map(String::from);
+						compression = attr(&attributes, "compression").map(String::from);
+						data.clear();
+					},
+					"tile" => {
+						if let Some(ref mut layer) = layer {
+							layer.gids.push(attr_num(&attributes, "gid").unwrap_or(0));
+						}
+					},
+					"object" => {
+						tiled.objects.push(TiledObject {
+							gid: attr_num(&attributes, "gid").unwrap_or(0),
+							x: try!(attr_num(&attributes, "x")),
+							y: try!(attr_num(&attributes, "y")),
+						});
+					},
+					_ => { },
+				}
+			},
+			XmlEvent::Characters(text) => { data.push_str(&text); },
+			XmlEvent::EndElement { name } => {
+				match name.local_name.as_str() {
+					"tileset" => { in_tileset = false; },
+					"data" => {
+						if let Some(ref mut layer) = layer {
+							match encoding.as_ref().map(|e| e.as_str()) {
+								Some("csv") => { layer.gids = try!(decode_csv(&data)); },
+								Some("base64") => { layer.gids = try!(decode_base64(&data, compression.as_ref().map(|c| c.as_str()))); },
+								Some(other) => { return Err(TiledError::UnsupportedEncoding(other.to_string())); },
+								None => { },
+							}
+						}
+					},
+					"layer" => {
+						if let Some(layer) = layer.take() {
+							tiled.layers.push(layer);
+						}
+					},
+					_ => { },
+				}
+			},
+			_ => { },
+		}
+	}
+
+	Ok(tiled)
+}
+
+fn json_num(value: &Json, key: &str) -> Result<f64, TiledError> {
+	match value.find(key).and_then(|v| v.as_f64()) {
+		Some(n) => Ok(n),
+		None => Err(TiledError::Malformed(format!("missing number '{}'", key))),
+	}
+}
+
+pub fn parse_json<R: Read>(mut reader: R) -> Result<TiledMap, TiledError> {
+	let root = try!(Json::from_reader(&mut reader));
+
+	if root.find("infinite").and_then(|v| v.as_boolean()) == Some(true) {
+		return Err(TiledError::UnsupportedEncoding(String::from("infinite maps")));
+	}
+
+	let mut tiled = TiledMap {
+		width: try!(json_num(&root, "width")) as i32,
+		height: try!(json_num(&root, "height")) as i32,
+		tile_width: try!(json_num(&root, "tilewidth")) as u32,
+		tile_height: try!(json_num(&root, "tileheight")) as u32,
+		tilesets: Vec::new(),
+		layers: Vec::new(),
+		objects: Vec::new(),
+	};
+
+	if let Some(tilesets) = root.find("tilesets").and_then(|v| v.as_array()) {
+		for tileset in tilesets {
+			let source = tileset.find("source").and_then(|v| v.as_string()).map(String::from);
+			let name = tileset.find("name").and_then(|v| v.as_string()).map(String::from).or(source.clone()).unwrap_or(String::new());
+			tiled.tilesets.push(TiledTileset {
+				first_gid: try!(json_num(tileset, "firstgid")) as u32,
+				name: name,
+				tile_width: json_num(tileset, "tilewidth").unwrap_or(0.0) as u32,
+				tile_height: json_num(tileset, "tileheight").unwrap_or(0.0) as u32,
+				tile_count: json_num(tileset, "tilecount").ok().map(|n| n as u32),
+				image: tileset.find("image").and_then(|v| v.as_string()).map(String::from),
+				source: source,
+			});
+		}
+	}
+
+	let layers = match root.find("layers").and_then(|v| v.as_array()) {
+		Some(layers) => layers,
+		None => { return Err(TiledError::Malformed(String::from("missing 'layers'"))); },
+	};
+
+	let mut seen_objects = false;
+	for layer in layers {
+		match layer.find("type").and_then(|v| v.as_string()) {
+			Some("tilelayer") => {
+				let name = layer.find("name").and_then(|v| v.as_string()).unwrap_or("").to_string();
+				let gids = match layer.find("data") {
+					Some(&Json::Array(ref values)) => {
+						let mut gids = Vec::with_capacity(values.len());
+						for value in values {
+							match value.as_u64() {
+								Some(gid) => gids.push(gid as u32),
+								None => { return Err(TiledError::Malformed(format!("bad gid in layer '{}'", name))); },
+							}
+						}
+						gids
+					},
+					Some(&Json::String(ref data)) => {
+						let compression = layer.find("compression").and_then(|v| v.as_string()).and_then(|c| if c.is_empty() { None } else { Some(c) });
+						try!(decode_base64(data, compression))
+					},
+					_ => { return Err(TiledError::Malformed(format!("layer '{}' has no data", name))); },
+				};
+				tiled.layers.push(TiledLayer {
+					name: name,
+					gids: gids,
+					visible: layer.find("visible").and_then(|v| v.as_boolean()).unwrap_or(true),
+					opacity: json_num(layer, "opacity").unwrap_or(1.0) as f32,
+					above_objects: seen_objects,
+				});
+			},
+			Some("objectgroup") => {
+				seen_objects = true;
+				if let Some(objects) = layer.find("objects").and_then(|v| v.as_array()) {
+					for object in objects {
+						tiled.objects.push(TiledObject {
+							gid: json_num(object, "gid").unwrap_or(0.0) as u32,
+							x: try!(json_num(object, "x")) as f32,
+							y: try!(json_num(object, "y")) as f32,
+						});
+					}
+				}
+			},
+			_ => { },
+		}
+	}
+
+	Ok(tiled)
+}
+
+#[cfg(test)]
+mod tests {
+	use map::Map;
+	use tiled::{load, parse_json, parse_tmx, TiledError, TiledMap};
+	use tile::TileAtlas;
+
+	const SMALL: &'static str = include_str!("../tests/fixtures/small.tmx");
+	const SMALL_JSON: &'static str = include_str!("../tests/fixtures/small.tmj");
+
+	fn check_small(map: &Map) {
+
+		assert_eq!((map.width, map.height), (3, 2));
+		assert_eq!(map.layers[map.ground].name, "ground");
+		assert_eq!(map.get(0, 1).map(|tile| tile.tex_id), Some(0));
+		assert_eq!(map.get(2, 0).map(|tile| tile.tex_id), Some(5));
+
+		let above = map.layer_index("above").unwrap();
+		assert!(map.layers[above].order > 0);
+		assert_eq!(map.layers[above].opacity, 0.5);
+		assert_eq!(map.get_layer(above, 1, 0).map(|tile| tile.tex_id), Some(12));
+
+		assert_eq!(map.entities().len(), 1);
+		assert_eq!((map.entities()[0].x, map.entities()[0].y, map.entities()[0].tex_id), (1.0, 0.0, 14));
+	}
+
+	#[test]
+	fn builds_the_fixture() {
+		let atlas = TileAtlas::new(16, 16);
+		check_small(&parse_tmx(SMALL.as_bytes()).unwrap().build(10.0, 1.0, &atlas).unwrap());
+	}
+
+	#[test]
+	fn builds_the_json_fixture() {
+		let atlas = TileAtlas::new(16, 16);
+		check_small(&parse_json(SMALL_JSON.as_bytes()).unwrap().build(10.0, 1.0, &atlas).unwrap());
+		check_small(&load("tests/fixtures/small.tmj", 10.0, 1.0, &atlas).unwrap());
+	}
+
+	#[test]
+	fn refuses_foreign_and_external_tilesets() {
+		let atlas = TileAtlas::new(16, 16);
+
+		let foreign = SMALL.replace("tilecount=\"16\" columns=\"4\"/>",
+			"tilecount=\"16\" columns=\"4\"/>\n <tileset firstgid=\"17\" name=\"props\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"4\"><image source=\"props.png\"/></tileset>")
+			.replace("4,5,6", "4,5,17");
+		match parse_tmx(foreign.as_bytes()).unwrap().build(10.0, 1.0, &atlas) {
+			Err(TiledError::UnknownTileset(ref name)) if name == "props" => { },
+			_ => panic!("a second tileset should not alias the atlas"),
+		}
+
+		let external = SMALL.replace("name=\"atlas\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"16\" columns=\"4\"", "source=\"atlas.tsx\"");
+		match parse_tmx(external.as_bytes()).unwrap().build(10.0, 1.0, &atlas) {
+			Err(TiledError::ExternalTileset(ref source)) if source == "atlas.tsx" => { },
+			_ => panic!("external tilesets should be refused"),
+		}
+	}
+
+	#[test]
+	fn refuses_empty_and_huge_maps() {
+		let atlas = TileAtlas::new(16, 16);
+		for &(width, height) in [(0, 4), (4, -1), (1 << 16, 1 << 16)].iter() {
+			let tiled = TiledMap {
+				width: width,
+				height: height,
+				tile_width: 16,
+				tile_height: 16,
+				tilesets: Vec::new(),
+				layers: Vec::new(),
+				objects: Vec::new(),
+			};
+			match tiled.build(10.0, 1.0, &atlas) {
+				Err(TiledError::Malformed(_)) => { },
+				_ => panic!("{}x{} should be refused", width, height),
+			}
+		}
+	}
+}
@@ -0,0 +1,85 @@
+/// Turns real elapsed time into a whole number of fixed simulation ticks.
+///
+/// Leftover time carries over to the next frame, and `alpha` says how far the
+/// simulation is between its last two ticks so drawing can interpolate.
+pub struct FixedStep {
+	/// Length of one tick in seconds.
+	pub dt: f32,
+	/// Most ticks run for one frame. Time beyond that is dropped, so a long
+	/// stall slows the game down instead of freezing it while it catches up.
+	pub max_steps: u32,
+	accumulator: f32,
+}
+
+impl FixedStep {
+	pub fn new(tick_rate: f32, max_steps: u32) -> FixedStep {
+		FixedStep {
+			dt: 1.0 / tick_rate,
+			max_steps: max_steps,
+			accumulator: 0.0,
+		}
+	}
+
+	/// Adds `elapsed` seconds and returns how many ticks to run.
+	pub fn advance(&mut self, elapsed: f32) -> u32 {
+		self.accumulator += elapsed;
+
+		let mut steps = 0;
+		while self.accumulator >= self.dt {
+			if steps == self.max_steps {
+				self.accumulator %= self.dt;
+				break;
+			}
+			self.accumulator -= self.dt;
+			steps += 1;
+		}
+		steps
+	}
+
+	/// Fraction of a tick since the last one, in [0, 1).
+	pub fn alpha(&self) -> f32 {
+		self.accumulator / self.dt
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use timestep::FixedStep;
+
+	#[test]
+	fn runs_whole_steps() {
+		let mut step = FixedStep::new(4.0, 10);
+		assert_eq!(step.advance(0.5), 2);
+		assert_eq!(step.alpha(), 0.0);
+		assert_eq!(step.advance(0.0), 0);
+	}
+
+	#[test]
+	fn carries_leftover_time() {
+		let mut step = FixedStep::new(4.0, 10);
+		assert_eq!(step.advance(0.375), 1);
+		assert_eq!(step.alpha(), 0.5);
+		assert_eq!(step.advance(0.125), 1);
+		assert_eq!(step.alpha(), 0.0);
+	}
+
+	#[test]
+	fn drops_time_beyond_max_steps() {
+		let mut step = FixedStep::new(4.0, 3);
+		assert_eq!(step.advance(2.125), 3);
+		assert_eq!(step.alpha(), 0.5);
+		assert_eq!(step.advance(0.125), 1);
+	}
+
+	#[test]
+	fn alpha_stays_below_one() {
+		let mut step = FixedStep::new(60.0, 5);
+		let mut elapsed = 0.0001;
+		for _ in 0..10000 {
+			step.advance(elapsed);
+			let alpha = step.alpha();
+			assert!(alpha >= 0.0 && alpha < 1.0, "alpha {} after {}", alpha, elapsed);
+			elapsed = (elapsed * 1.37) % 0.2;
+		}
+	}
+}
@@ -6,6 +6,34 @@ pub fn translate(x: i32, y: i32, width: i32) -> usize {
     ((y * width) + x) as usize
 }
 
+/// Cells on the line from `a` to `b`, both ends included.
+pub fn line_cells(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+	let mut cells = Vec::new();
+	let (mut x, mut y) = a;
+	let dx = (b.0 - a.0).abs();
+	let dy = -(b.1 - a.1).abs();
+	let sx = if a.0 < b.0 { 1 } else { -1 };
+	let sy = if a.1 < b.1 { 1 } else { -1 };
+	let mut err = dx + dy;
+
+	loop {
+		cells.push((x, y));
+		if x == b.0 && y == b.1 {
+			break;
+		}
+		let e2 = 2 * err;
+		if e2 >= dy {
+			err += dy;
+			x += sx;
+		}
+		if e2 <= dx {
+			err += dx;
+			y += sy;
+		}
+	}
+	cells
+}
+
 pub struct Rectangle<T> {
 	pub x: T,
 	pub y: T,
@@ -24,6 +52,7 @@ impl<T> Rectangle<T> {
 	}
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct V2<T> {
 	pub x: T,
 	pub y: T,
@@ -0,0 +1,65 @@
+extern crate tilepaste;
+
+use tilepaste::fog::Sight;
+use tilepaste::game::Game;
+use tilepaste::gen::GridGenerator;
+use tilepaste::harness::Harness;
+use tilepaste::keyboard::Action;
+use tilepaste::tile::TileAtlas;
+use tilepaste::utils::V2;
+
+fn game<'a>(atlas: &'a TileAtlas) -> Game<'a> {
+	Game::new(16.0 / 9.0, atlas, &GridGenerator::new(), 0)
+}
+
+#[test]
+fn holding_right_moves_right() {
+	let atlas = TileAtlas::new(16, 16);
+	let mut harness = Harness::new(game(&atlas));
+	let start = harness.game.player.pos.x;
+
+	harness.hold(Action::Right, 30);
+
+	assert!(harness.game.player.pos.x > start);
+	assert_eq!(harness.ticks, 30);
+}
+
+#[test]
+fn falls_to_the_bottom_of_the_world() {
+	let atlas = TileAtlas::new(16, 16);
+	let mut harness = Harness::new(game(&atlas));
+	harness.game.player.pos = V2::new(3.0, 10.0);
+
+	assert!(harness.run_until(600, |game| game.player.contacts.grounded));
+	assert_eq!(harness.game.player.pos.y, 0.0);
+	assert_eq!(harness.game.player.vel.y, 0.0);
+}
+
+#[test]
+fn lands_on_a_solid_tile() {
+	let atlas = TileAtlas::new(16, 16);
+	let mut harness = Harness::new(game(&atlas));
+	harness.game.map.set(3, 4, 11).unwrap();
+	harness.game.player.pos = V2::new(3.0, 10.0);
+
+	assert!(harness.run_until(600, |game| game.player.contacts.grounded));
+	assert_eq!(harness.game.player.pos.y, 5.0);
+
+	// Standing still on it stays put.
+	harness.run(30);
+	assert_eq!(harness.game.player.pos.y, 5.0);
+	assert!(harness.game.player.contacts.grounded);
+}
+
+#[test]
+fn reveals_fog_around_the_player_wherever_the_view_is() {
+	let atlas = TileAtlas::new(16, 16);
+	let mut harness = Harness::new(game(&atlas));
+	harness.game.map.view.x = 40.0;
+	harness.game.map.view.y = 20.0;
+	harness.game.player.pos = V2::new(3.0, 2.0);
+
+	harness.run(1);
+	assert_eq!(harness.game.map.sight(3, 2), Sight::Visible);
+	assert_eq!(harness.game.map.sight(43, 22), Sight::Unexplored);
+}